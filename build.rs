@@ -0,0 +1,32 @@
+use std::fs;
+use std::path::Path;
+
+// Parses and validates the WGSL shaders, so mistakes fail the build instead
+// of panicking when the pipelines are created.
+fn main() {
+    let shader_dir = Path::new("src/shaders");
+    println!("cargo:rerun-if-changed={}", shader_dir.display());
+    for entry in fs::read_dir(shader_dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.extension().map_or(true, |e| e != "wgsl") {
+            continue;
+        }
+        println!("cargo:rerun-if-changed={}", path.display());
+        let source = fs::read_to_string(&path).unwrap();
+        let module = match naga::front::wgsl::parse_str(&source) {
+            Ok(module) => module,
+            Err(e) => {
+                e.emit_to_stderr(&source);
+                panic!("failed to parse {}", path.display());
+            }
+        };
+        if let Err(e) = naga::valid::Validator::new(
+            naga::valid::ValidationFlags::all(),
+            naga::valid::Capabilities::empty(),
+        )
+        .validate(&module)
+        {
+            panic!("invalid shader {}: {:?}", path.display(), e);
+        }
+    }
+}
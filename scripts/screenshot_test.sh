@@ -0,0 +1,41 @@
+#!/bin/sh
+# Renders a few fixed views with `--screenshot` and compares every frame
+# to its reference in tests/screenshots. Run with `--update` to replace the
+# references after an intended change to the rendering.
+set -e
+cd "$(dirname "$0")/.."
+
+references=tests/screenshots
+output=target/screenshots
+mkdir -p "$references" "$output"
+cargo build --release --quiet
+
+update=false
+if [ "$1" = "--update" ]; then
+    update=true
+fi
+
+failed=0
+# Name and options of each view.
+while read -r name options; do
+    if $update; then
+        # Options are split on purpose.
+        # shellcheck disable=SC2086
+        cargo run --release --quiet -- --screenshot "$references/$name.png" \
+            --size 640x360 $options </dev/null
+    elif [ ! -f "$references/$name.png" ]; then
+        echo "$name: no reference, create it with --update"
+        failed=1
+    else
+        # shellcheck disable=SC2086
+        cargo run --release --quiet -- --screenshot "$output/$name.png" \
+            --size 640x360 $options --compare "$references/$name.png" </dev/null ||
+            failed=1
+    fi
+done <<EOF
+noon --pos 0,40,0 --look 270,-20 --hour 12
+evening --pos 0,40,0 --look 180,-10 --hour 18
+night --pos 0,40,0 --look 270,-20 --hour 0
+looking_down --pos 16,60,16 --look 45,-80 --hour 12
+EOF
+exit $failed
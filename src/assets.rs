@@ -0,0 +1,96 @@
+use anyhow::{Context, Result};
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Overrides the asset root, the `--assets <dir>` flag takes precedence.
+const ASSETS_ENV: &str = "VOXEL_ASSETS";
+const ASSETS_DIR: &str = "res";
+const MISSING_TEXTURE_SIZE: u32 = 16;
+
+/// Resolves asset paths against one root directory and caches the
+/// loaded images.
+pub struct Assets {
+    root: PathBuf,
+    images: HashMap<String, DynamicImage>,
+}
+
+impl Assets {
+    /// Uses the first of the `--assets <dir>` argument, the `VOXEL_ASSETS`
+    /// environment variable, a `res` directory next to the executable or in
+    /// the current directory, and the `res` directory of the source tree.
+    pub fn new() -> Self {
+        let mut args = std::env::args().skip_while(|a| a != "--assets").skip(1);
+        let root = args
+            .next()
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os(ASSETS_ENV).map(PathBuf::from))
+            .or_else(|| {
+                let exe = std::env::current_exe().ok()?;
+                Some(exe.parent()?.join(ASSETS_DIR)).filter(|p| p.is_dir())
+            })
+            .or_else(|| {
+                let dir = std::env::current_dir().ok()?.join(ASSETS_DIR);
+                Some(dir).filter(|p| p.is_dir())
+            })
+            .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join(ASSETS_DIR));
+        Self::with_root(root)
+    }
+
+    pub fn with_root(root: PathBuf) -> Self {
+        Self {
+            root,
+            images: HashMap::new(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn path(&self, name: &str) -> PathBuf {
+        self.root.join(name)
+    }
+
+    pub fn image(&mut self, name: &str) -> Result<&DynamicImage> {
+        if !self.images.contains_key(name) {
+            let path = self.path(name);
+            let image = image::open(&path)
+                .with_context(|| format!("Couldn't load image {}", path.display()))?;
+            self.images.insert(name.to_string(), image);
+        }
+        Ok(&self.images[name])
+    }
+
+    /// Like `image`, but reports the error and returns a checkerboard
+    /// texture if the image can't be loaded.
+    pub fn image_or_missing(&mut self, name: &str) -> DynamicImage {
+        match self.image(name) {
+            Ok(image) => image.clone(),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                Assets::missing_texture()
+            }
+        }
+    }
+
+    /// Drops the cached images so they are loaded from disk again.
+    pub fn clear_cache(&mut self) {
+        self.images.clear();
+    }
+
+    fn missing_texture() -> DynamicImage {
+        let half = MISSING_TEXTURE_SIZE / 2;
+        DynamicImage::ImageRgba8(RgbaImage::from_fn(
+            MISSING_TEXTURE_SIZE,
+            MISSING_TEXTURE_SIZE,
+            |x, y| {
+                if (x < half) == (y < half) {
+                    Rgba([255, 0, 255, 255])
+                } else {
+                    Rgba([0, 0, 0, 255])
+                }
+            },
+        ))
+    }
+}
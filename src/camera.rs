@@ -1,25 +1,66 @@
+use crate::input::{Action, InputState};
+use crate::physics;
 use crate::renderer::graphics::Graphics;
+use crate::world::World;
 use nalgebra::{Matrix4, Point3, Vector3};
-use winit::event::{DeviceEvent, KeyboardInput, MouseScrollDelta, VirtualKeyCode};
+use winit::event::{DeviceEvent, MouseScrollDelta};
 
 pub struct Camera {
     pub eye: Point3<f32>,
     pub target: Point3<f32>,
     up: Vector3<f32>,
     pub aspect: f32,
+    /// Vertical field of view in degrees.
     pub fov: f32,
     near: f32,
     far: f32,
     pub controller: CameraController,
     pub global_matrix: Matrix4<f32>,
+    pub mode: CameraMode,
+    // Point the orbit camera circles around after the last and the step
+    // before it.
+    focus: Point3<f32>,
+    previous_focus: Point3<f32>,
+    orbit_distance: f32,
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum CameraMode {
+    FirstPerson,
+    /// Follows behind the player, pulled closer where terrain is in the
+    /// way.
+    ThirdPerson,
+    /// Circles around a point, moved with the movement keys, while the
+    /// player stands still. Meant for inspecting chunk meshes.
+    Orbit,
+}
+
+const MIN_FOV: f32 = 30.;
+const MAX_FOV: f32 = 110.;
+// Degrees the field of view changes by per line scrolled.
+const FOV_STEP: f32 = 5.;
+// Scrolled pixels that count as one line.
+const PIXELS_PER_LINE: f32 = 20.;
+const THIRD_PERSON_DISTANCE: f32 = 4.;
+// Space kept between the third-person camera and the terrain behind it.
+const THIRD_PERSON_MARGIN: f32 = 0.2;
+const MIN_ORBIT_DISTANCE: f32 = 2.;
+const MAX_ORBIT_DISTANCE: f32 = 128.;
+// Share of the orbit distance left after scrolling in by one line.
+const ORBIT_ZOOM: f32 = 0.9;
+// Cubes per second the orbit focus moves at.
+const ORBIT_SPEED: f32 = 16.;
+
+/// Maps the OpenGL depth range of nalgebra's projections, -1 to 1, to the
+/// 0 to 1 range wgpu expects: `z' = 0.5 * z + 0.5 * w`. `Matrix4::new`
+/// takes its arguments in row-major order. Shared with the shadow cascades
+/// so both project depth the same way.
 #[rustfmt::skip]
-const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
+pub const OPENGL_TO_WGPU_MATRIX: Matrix4<f32> = Matrix4::new(
     1.0, 0.0, 0.0, 0.0,
     0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.0,
-    0.0, 0.0, 0.5, 1.0,
+    0.0, 0.0, 0.5, 0.5,
+    0.0, 0.0, 0.0, 1.0,
 );
 
 impl Camera {
@@ -35,6 +76,10 @@ impl Camera {
             far: 100.0,
             controller,
             global_matrix: OPENGL_TO_WGPU_MATRIX,
+            mode: CameraMode::FirstPerson,
+            focus: Point3::origin(),
+            previous_focus: Point3::origin(),
+            orbit_distance: 16.,
         }
     }
 
@@ -45,42 +90,138 @@ impl Camera {
             self.eye.z + self.target.z,
         );
         let projection =
-            Matrix4::new_perspective(self.aspect, self.fov.to_degrees(), self.near, self.far);
+            Matrix4::new_perspective(self.aspect, self.fov.to_radians(), self.near, self.far);
         let view = Matrix4::look_at_rh(&self.eye, &target, &self.up);
         self.global_matrix = OPENGL_TO_WGPU_MATRIX * projection * view;
     }
 
+    /// Near and far plane distances.
+    pub fn depth_range(&self) -> (f32, f32) {
+        (self.near, self.far)
+    }
+
+    pub fn set_far(&mut self, far: f32) {
+        self.far = far;
+        self.update_global_matrix();
+    }
+
     pub fn resize(&mut self, graphics: &Graphics) {
         self.aspect = graphics.surface_config.width as f32 / graphics.surface_config.height as f32;
     }
 
+    /// Applies the controller's zoom and look direction. Scrolling up
+    /// zooms in, the orbit camera moves closer instead.
     pub fn update(&mut self) {
-        self.fov += self.controller.fov_delta;
-        self.controller.fov_delta = 0.;
+        let scrolled = self.controller.scrolled;
+        if self.mode == CameraMode::Orbit {
+            self.orbit_distance = (self.orbit_distance * ORBIT_ZOOM.powf(scrolled))
+                .clamp(MIN_ORBIT_DISTANCE, MAX_ORBIT_DISTANCE);
+        } else {
+            self.fov = (self.fov - scrolled * FOV_STEP).clamp(MIN_FOV, MAX_FOV);
+        }
+        self.controller.scrolled = 0.;
         self.target = Point3::new(
             self.controller.yaw.to_radians().cos() * self.controller.pitch.to_radians().cos(),
             self.controller.pitch.to_radians().sin(),
             self.controller.yaw.to_radians().sin() * self.controller.pitch.to_radians().cos(),
         );
-        let target = Vector3::new(self.target.x, 0.0, self.target.z).normalize();
-        self.eye +=
-            &target * self.controller.speed * (self.controller.forward - self.controller.backward);
-        self.eye += &target.cross(&self.up)
-            * self.controller.speed
-            * (self.controller.right - self.controller.left);
-        self.eye += Vector3::new(0.0, 1.0, 0.0)
-            * self.controller.speed
-            * (self.controller.up - self.controller.down);
         self.update_global_matrix();
     }
 
+    /// Places the eye for the current mode, `player_eye` is the player's
+    /// eye and `alpha` how far the frame is between the last two steps.
+    pub fn follow(&mut self, world: &World, player_eye: Point3<f32>, alpha: f32) {
+        let forward = self.target.coords;
+        self.eye = match self.mode {
+            CameraMode::FirstPerson => player_eye,
+            CameraMode::ThirdPerson => {
+                let distance = physics::raycast(world, player_eye, -forward, THIRD_PERSON_DISTANCE)
+                    .map_or(THIRD_PERSON_DISTANCE, |hit| hit.distance);
+                player_eye - forward * (distance - THIRD_PERSON_MARGIN).max(0.)
+            }
+            CameraMode::Orbit => {
+                let focus = self.previous_focus + (self.focus - self.previous_focus) * alpha;
+                focus - forward * self.orbit_distance
+            }
+        };
+        self.update_global_matrix();
+    }
+
+    /// Switches to the next mode, the orbit camera starts circling around
+    /// `player_eye`.
+    pub fn cycle_mode(&mut self, player_eye: Point3<f32>) {
+        self.mode = match self.mode {
+            CameraMode::FirstPerson => CameraMode::ThirdPerson,
+            CameraMode::ThirdPerson => CameraMode::Orbit,
+            CameraMode::Orbit => CameraMode::FirstPerson,
+        };
+        self.focus = player_eye;
+        self.previous_focus = player_eye;
+    }
+
+    /// Moves the orbit focus along the pressed movement keys for `dt`
+    /// seconds.
+    pub fn step(&mut self, dt: f32) {
+        self.previous_focus = self.focus;
+        if self.mode == CameraMode::Orbit {
+            self.focus += self.key_direction(true) * ORBIT_SPEED * dt;
+        }
+    }
+
+    /// Horizontal direction of the pressed movement keys relative to where
+    /// the camera looks, at most one unit long. Nothing while orbiting.
+    pub fn walk_direction(&self) -> Vector3<f32> {
+        self.player_direction(false)
+    }
+
+    /// Like `walk_direction`, but also up and down.
+    pub fn fly_direction(&self) -> Vector3<f32> {
+        self.player_direction(true)
+    }
+
+    /// Whether the player should jump or move up, never while orbiting.
+    pub fn jumping(&self) -> bool {
+        self.mode != CameraMode::Orbit && self.controller.jumping()
+    }
+
+    // The orbit camera takes the movement keys for itself.
+    fn player_direction(&self, vertical: bool) -> Vector3<f32> {
+        if self.mode == CameraMode::Orbit {
+            Vector3::zeros()
+        } else {
+            self.key_direction(vertical)
+        }
+    }
+
+    fn key_direction(&self, vertical: bool) -> Vector3<f32> {
+        let yaw = self.controller.yaw.to_radians();
+        let forward = Vector3::new(yaw.cos(), 0.0, yaw.sin());
+        let direction = forward * (self.controller.forward - self.controller.backward)
+            + forward.cross(&self.up) * (self.controller.right - self.controller.left);
+        let direction = if direction.norm() > 1. {
+            direction.normalize()
+        } else {
+            direction
+        };
+        if vertical {
+            direction + self.up * (self.controller.up - self.controller.down)
+        } else {
+            direction
+        }
+    }
+
     pub fn input(&mut self, event: &winit::event::DeviceEvent) {
         self.controller.process_input(event);
     }
 }
 
+// Degrees per second the look actions turn at when fully held.
+const LOOK_SPEED: f32 = 180.;
+// Movement speed multipliers.
+const SPRINT_FACTOR: f32 = 1.6;
+const CROUCH_FACTOR: f32 = 0.3;
+
 pub struct CameraController {
-    speed: f32,
     sensitivity: f64,
     forward: f32,
     backward: f32,
@@ -88,15 +229,17 @@ pub struct CameraController {
     right: f32,
     up: f32,
     down: f32,
+    sprint: bool,
+    crouch: bool,
     pub yaw: f32,
     pub pitch: f32,
-    fov_delta: f32,
+    // Lines scrolled since the last update.
+    scrolled: f32,
 }
 
 impl CameraController {
     pub fn new() -> Self {
         CameraController {
-            speed: 0.4,
             sensitivity: 0.1,
             forward: 0.,
             backward: 0.,
@@ -104,74 +247,86 @@ impl CameraController {
             right: 0.,
             up: 0.,
             down: 0.,
+            sprint: false,
+            crouch: false,
             yaw: 270.0,
             pitch: 0.0,
-            fov_delta: 0.,
+            scrolled: 0.,
+        }
+    }
+
+    /// Whether the action for moving up, which also jumps, is held.
+    pub fn jumping(&self) -> bool {
+        self.up > 0.
+    }
+
+    pub fn crouching(&self) -> bool {
+        self.crouch
+    }
+
+    /// Multiplier of the movement speed from sprinting or crouching,
+    /// crouching wins if both are held.
+    pub fn speed_factor(&self) -> f32 {
+        if self.crouch {
+            CROUCH_FACTOR
+        } else if self.sprint {
+            SPRINT_FACTOR
+        } else {
+            1.
+        }
+    }
+
+    /// Takes the movement from the held actions and turns along the look
+    /// actions for `dt` seconds.
+    pub fn apply_actions(&mut self, input: &InputState, dt: f32) {
+        self.forward = input.value(Action::MoveForward);
+        self.backward = input.value(Action::MoveBackward);
+        self.left = input.value(Action::MoveLeft);
+        self.right = input.value(Action::MoveRight);
+        self.up = input.value(Action::Jump);
+        self.down = input.value(Action::Descend);
+        self.sprint = input.value(Action::Sprint) > 0.;
+        self.crouch = input.value(Action::Crouch) > 0.;
+        let yaw = input.value(Action::LookRight) - input.value(Action::LookLeft);
+        let pitch = input.value(Action::LookUp) - input.value(Action::LookDown);
+        if yaw != 0. || pitch != 0. {
+            self.turn(yaw * LOOK_SPEED * dt, pitch * LOOK_SPEED * dt);
         }
     }
 
     pub fn process_input(&mut self, event: &winit::event::DeviceEvent) {
         match event {
-            DeviceEvent::MouseMotion { delta } => {
-                self.yaw += (delta.0 * self.sensitivity) as f32;
-                self.pitch -= (delta.1 * self.sensitivity) as f32;
-
-                if self.pitch > 89.0 {
-                    self.pitch = 89.0;
-                } else if self.pitch < -89.0 {
-                    self.pitch = -89.0;
-                }
-
-                if self.yaw > 360.0 {
-                    self.yaw = 0.0;
-                } else if self.yaw < 0.0 {
-                    self.yaw = 360.0;
-                }
-            }
+            DeviceEvent::MouseMotion { delta } => self.turn(
+                (delta.0 * self.sensitivity) as f32,
+                -(delta.1 * self.sensitivity) as f32,
+            ),
             DeviceEvent::MouseWheel { delta } => {
-                self.fov_delta = match delta {
+                self.scrolled += match delta {
                     MouseScrollDelta::LineDelta(_, scroll) => *scroll,
                     MouseScrollDelta::PixelDelta(winit::dpi::PhysicalPosition { y, .. }) => {
-                        *y as f32
+                        *y as f32 / PIXELS_PER_LINE
                     }
                 }
             }
-            DeviceEvent::Motion { .. } => {}
-            DeviceEvent::Button { .. } => {}
-            DeviceEvent::Key(KeyboardInput {
-                state,
-                virtual_keycode,
-                ..
-            }) => {
-                let value: f32;
-                if *state == winit::event::ElementState::Pressed {
-                    value = 1.
-                } else {
-                    value = 0.;
-                }
-                match virtual_keycode.unwrap() {
-                    VirtualKeyCode::Space => {
-                        self.up = value;
-                    }
-                    VirtualKeyCode::LShift => {
-                        self.down = value;
-                    }
-                    VirtualKeyCode::W => {
-                        self.forward = value;
-                    }
-                    VirtualKeyCode::S => {
-                        self.backward = value;
-                    }
-                    VirtualKeyCode::A => {
-                        self.left = value;
-                    }
-                    VirtualKeyCode::D => {
-                        self.right = value;
-                    }
-                    _ => (),
-                }
-            }
             _ => (),
         }
     }
+
+    // Turns by degrees, looking straight up or down is avoided.
+    fn turn(&mut self, yaw: f32, pitch: f32) {
+        self.yaw += yaw;
+        self.pitch += pitch;
+
+        if self.pitch > 89.0 {
+            self.pitch = 89.0;
+        } else if self.pitch < -89.0 {
+            self.pitch = -89.0;
+        }
+
+        if self.yaw > 360.0 {
+            self.yaw = 0.0;
+        } else if self.yaw < 0.0 {
+            self.yaw = 360.0;
+        }
+    }
 }
@@ -1,9 +1,11 @@
-use crate::coordinate::{ChunkCoord3D, Coord3DI};
-use crate::cube::{Cube, CubeType};
+use crate::coordinate::{ChunkCoord3D, Coord3DF, Coord3DI};
+use crate::cube::{Cube, CubeType, Transparency};
 use crate::perlin_noise;
 use crate::quad::{Quad, Rotation};
+use crate::renderer::instance::InstanceRaw;
 use crate::texture;
 use crate::uniform::{RenderPassData, SetUniforms};
+use crate::visibility::ChunkVisibility;
 use crate::world::CHUNK_USIZE;
 use std::sync::Arc;
 use wgpu::util::DeviceExt;
@@ -12,6 +14,7 @@ const CHUNK_LENGTH: usize = CHUNK_USIZE;
 const CHUNK_WIDTH: usize = CHUNK_USIZE;
 const CHUNK_HEIGHT: usize = CHUNK_USIZE;
 
+#[derive(Clone)]
 pub struct Chunk {
     pub position: ChunkCoord3D,
     cubes: Vec<Cube>,
@@ -24,6 +27,13 @@ impl Chunk {
         Self { position, cubes }
     }
 
+    /// Chunk filled with air, for tests.
+    #[cfg(test)]
+    pub fn empty(position: ChunkCoord3D) -> Self {
+        let cubes = vec![Cube::new(CubeType::AIR); CHUNK_LENGTH * CHUNK_WIDTH * CHUNK_HEIGHT];
+        Self { position, cubes }
+    }
+
     fn generate_terrain(cubes: &mut Vec<Cube>, pos: ChunkCoord3D) {
         let noise: Vec<f32>;
         if !std::is_x86_feature_detected!("avx2") {
@@ -49,194 +59,182 @@ impl Chunk {
         &self,
         device: Arc<wgpu::Device>,
         adjacent_chunks: Vec<Option<Arc<Chunk>>>,
+        lod: u32,
     ) -> ChunkMesh {
         let world_pos = self.position.to_world_position_i32();
-        let faces = self.cull_unseen_triangles(world_pos, adjacent_chunks);
+        let faces = if lod == 0 {
+            self.cull_unseen_triangles(world_pos, adjacent_chunks)
+        } else {
+            self.downsampled_faces(world_pos, lod)
+        };
+        let visibility = ChunkVisibility::from_cubes(&self.cubes);
 
-        ChunkMesh::new(&device, faces)
+        ChunkMesh::new(&device, faces, visibility, lod)
+    }
+
+    // Merges blocks of `2^lod` cubes into one cell which is solid when at
+    // least half of its cubes are. Faces on the chunk border are always
+    // emitted so there are no cracks next to chunks of another LOD level.
+    fn downsampled_faces(&self, world_pos: Coord3DI, lod: u32) -> Faces {
+        let step = 1 << lod;
+        let size = CHUNK_USIZE / step;
+        let mut cells = Vec::with_capacity(size * size * size);
+        for cy in 0..size {
+            for cz in 0..size {
+                for cx in 0..size {
+                    let mut solid = 0;
+                    let mut cube_type = CubeType::AIR;
+                    for y in cy * step..(cy + 1) * step {
+                        for z in cz * step..(cz + 1) * step {
+                            for x in cx * step..(cx + 1) * step {
+                                let t = self.cubes
+                                    [x + CHUNK_USIZE * z + CHUNK_USIZE * CHUNK_USIZE * y]
+                                    .cube_type;
+                                if t != CubeType::AIR {
+                                    solid += 1;
+                                    cube_type = t;
+                                }
+                            }
+                        }
+                    }
+                    if solid * 2 >= step * step * step {
+                        cells.push(cube_type);
+                    } else {
+                        cells.push(CubeType::AIR);
+                    }
+                }
+            }
+        }
+
+        let cell = |x: i32, y: i32, z: i32| -> CubeType {
+            let s = size as i32;
+            if x < 0 || y < 0 || z < 0 || x >= s || y >= s || z >= s {
+                return CubeType::AIR;
+            }
+            cells[(x + s * z + s * s * y) as usize]
+        };
+        let scale = step as f32;
+        let offset = (scale - 1.) / 2.;
+        let mut faces = Faces::new();
+        for y in 0..size as i32 {
+            for z in 0..size as i32 {
+                for x in 0..size as i32 {
+                    let cube_type = cell(x, y, z);
+                    if cube_type == CubeType::AIR {
+                        continue;
+                    }
+                    let texture_index = unsafe { texture::TEXTURE_INDEX_LIST[cube_type as usize] };
+                    let pos = Coord3DF::new(
+                        world_pos.x as f32 + x as f32 * scale + offset,
+                        world_pos.y as f32 + y as f32 * scale + offset,
+                        world_pos.z as f32 + z as f32 * scale + offset,
+                    );
+                    let neighbours = [
+                        ((x - 1, y, z), Rotation::LEFT, texture_index[0]),
+                        ((x + 1, y, z), Rotation::RIGHT, texture_index[1]),
+                        ((x, y, z - 1), Rotation::BACK, texture_index[2]),
+                        ((x, y, z + 1), Rotation::FRONT, texture_index[3]),
+                        ((x, y + 1, z), Rotation::UP, texture_index[4]),
+                        ((x, y - 1, z), Rotation::DOWN, texture_index[5]),
+                    ];
+                    for ((nx, ny, nz), rotation, index) in neighbours.iter() {
+                        if !cube_type.is_face_hidden(cell(*nx, *ny, *nz)) {
+                            faces.push(cube_type, Quad::new(pos, *rotation, *index, scale));
+                        }
+                    }
+                }
+            }
+        }
+        faces
+    }
+
+    fn cube_type_at(&self, x: usize, y: usize, z: usize) -> CubeType {
+        self.cube(x, y, z).cube_type
+    }
+
+    pub fn cube(&self, x: usize, y: usize, z: usize) -> &Cube {
+        &self.cubes[x + CHUNK_USIZE * z + CHUNK_USIZE * CHUNK_USIZE * y]
+    }
+
+    pub fn set_cube(&mut self, x: usize, y: usize, z: usize, cube: Cube) {
+        self.cubes[x + CHUNK_USIZE * z + CHUNK_USIZE * CHUNK_USIZE * y] = cube;
     }
 
     fn cull_unseen_triangles(
         &self,
         world_pos: Coord3DI,
         adjacent_chunks: Vec<Option<Arc<Chunk>>>,
-    ) -> Vec<Quad> {
-        let mut faces = Vec::new();
+    ) -> Faces {
+        // Type of the neighbouring cube in the given direction. Cubes outside
+        // of the loaded chunks are treated as air.
+        let neighbour = |x: usize, y: usize, z: usize, rotation: Rotation| -> CubeType {
+            let last = CHUNK_USIZE - 1;
+            match rotation {
+                Rotation::LEFT if x == 0 => adjacent_chunks[0]
+                    .as_ref()
+                    .map_or(CubeType::AIR, |c| c.cube_type_at(last, y, z)),
+                Rotation::LEFT => self.cube_type_at(x - 1, y, z),
+                Rotation::RIGHT if x == last => adjacent_chunks[1]
+                    .as_ref()
+                    .map_or(CubeType::AIR, |c| c.cube_type_at(0, y, z)),
+                Rotation::RIGHT => self.cube_type_at(x + 1, y, z),
+                Rotation::BACK if z == 0 => adjacent_chunks[2]
+                    .as_ref()
+                    .map_or(CubeType::AIR, |c| c.cube_type_at(x, y, last)),
+                Rotation::BACK => self.cube_type_at(x, y, z - 1),
+                Rotation::FRONT if z == last => adjacent_chunks[3]
+                    .as_ref()
+                    .map_or(CubeType::AIR, |c| c.cube_type_at(x, y, 0)),
+                Rotation::FRONT => self.cube_type_at(x, y, z + 1),
+                Rotation::UP if y == last => CubeType::AIR,
+                Rotation::UP => self.cube_type_at(x, y + 1, z),
+                Rotation::DOWN if y == 0 => CubeType::AIR,
+                Rotation::DOWN => self.cube_type_at(x, y - 1, z),
+            }
+        };
+
+        let mut faces = Faces::new();
         for y in 0..CHUNK_HEIGHT {
             let pos_y = y as i32 + world_pos.y;
             for z in 0..CHUNK_WIDTH {
                 let pos_z = z as i32 + world_pos.z;
                 for x in 0..CHUNK_LENGTH {
-                    let cube_ref = &self.cubes[x + CHUNK_USIZE * z + CHUNK_USIZE * CHUNK_USIZE * y];
-                    if cube_ref.cube_type == CubeType::AIR {
+                    let cube_type = self.cube_type_at(x, y, z);
+                    if cube_type == CubeType::AIR {
                         continue;
                     }
-                    let texture_index =
-                        unsafe { texture::TEXTURE_INDEX_LIST[cube_ref.cube_type as usize] };
-                    let pos_x = x as i32 + world_pos.x;
-                    if x > 0 {
-                        if self.cubes[(x - 1) + CHUNK_USIZE * z + CHUNK_USIZE * CHUNK_USIZE * y]
-                            .cube_type
-                            == CubeType::AIR
-                        {
-                            faces.push(Quad::new(
-                                Coord3DI::new(pos_x, pos_y, pos_z),
-                                Rotation::LEFT,
-                                texture_index[0],
-                            ));
-                        }
-                    } else {
-                        if let Some(c) = &adjacent_chunks[0] {
-                            if c.cubes[(CHUNK_USIZE - 1)
-                                + CHUNK_USIZE * z
-                                + CHUNK_USIZE * CHUNK_USIZE * y]
-                                .cube_type
-                                == CubeType::AIR
-                            {
-                                faces.push(Quad::new(
-                                    Coord3DI::new(pos_x, pos_y, pos_z),
-                                    Rotation::LEFT,
-                                    texture_index[0],
-                                ));
-                            }
-                        } else {
-                            faces.push(Quad::new(
-                                Coord3DI::new(pos_x, pos_y, pos_z),
-                                Rotation::LEFT,
-                                texture_index[0],
-                            ));
-                        }
-                    }
-                    if x < CHUNK_USIZE - 1 {
-                        if self.cubes[(x + 1) + CHUNK_USIZE * z + CHUNK_USIZE * CHUNK_USIZE * y]
-                            .cube_type
-                            == CubeType::AIR
-                        {
-                            faces.push(Quad::new(
-                                Coord3DI::new(pos_x, pos_y, pos_z),
-                                Rotation::RIGHT,
-                                texture_index[1],
-                            ));
-                        }
-                    } else {
-                        if let Some(c) = &adjacent_chunks[1] {
-                            if c.cubes[0 + CHUNK_USIZE * z + CHUNK_USIZE * CHUNK_USIZE * y]
-                                .cube_type
-                                == CubeType::AIR
-                            {
-                                faces.push(Quad::new(
-                                    Coord3DI::new(pos_x, pos_y, pos_z),
-                                    Rotation::RIGHT,
-                                    texture_index[1],
-                                ));
-                            }
-                        } else {
-                            faces.push(Quad::new(
-                                Coord3DI::new(pos_x, pos_y, pos_z),
-                                Rotation::RIGHT,
-                                texture_index[1],
-                            ));
-                        }
-                    }
-                    if z > 0 {
-                        if self.cubes[x + CHUNK_USIZE * (z - 1) + CHUNK_USIZE * CHUNK_USIZE * y]
-                            .cube_type
-                            == CubeType::AIR
-                        {
-                            faces.push(Quad::new(
-                                Coord3DI::new(pos_x, pos_y, pos_z),
-                                Rotation::BACK,
-                                texture_index[2],
-                            ));
-                        }
-                    } else {
-                        if let Some(c) = &adjacent_chunks[2] {
-                            if c.cubes[x
-                                + CHUNK_USIZE * (CHUNK_USIZE - 1)
-                                + CHUNK_USIZE * CHUNK_USIZE * y]
-                                .cube_type
-                                == CubeType::AIR
-                            {
-                                faces.push(Quad::new(
-                                    Coord3DI::new(pos_x, pos_y, pos_z),
-                                    Rotation::BACK,
-                                    texture_index[2],
-                                ));
-                            }
-                        } else {
-                            faces.push(Quad::new(
-                                Coord3DI::new(pos_x, pos_y, pos_z),
-                                Rotation::BACK,
-                                texture_index[2],
-                            ));
-                        }
-                    }
-                    if z < CHUNK_USIZE - 1 {
-                        if self.cubes[x + CHUNK_USIZE * (z + 1) + CHUNK_USIZE * CHUNK_USIZE * y]
-                            .cube_type
-                            == CubeType::AIR
-                        {
-                            faces.push(Quad::new(
-                                Coord3DI::new(pos_x, pos_y, pos_z),
-                                Rotation::FRONT,
-                                texture_index[3],
-                            ));
-                        }
-                    } else {
-                        if let Some(c) = &adjacent_chunks[3] {
-                            if c.cubes[x + CHUNK_USIZE * 0 + CHUNK_USIZE * CHUNK_USIZE * y]
-                                .cube_type
-                                == CubeType::AIR
-                            {
-                                faces.push(Quad::new(
-                                    Coord3DI::new(pos_x, pos_y, pos_z),
-                                    Rotation::FRONT,
-                                    texture_index[3],
-                                ));
-                            }
-                        } else {
-                            faces.push(Quad::new(
-                                Coord3DI::new(pos_x, pos_y, pos_z),
-                                Rotation::FRONT,
-                                texture_index[3],
-                            ));
-                        }
-                    }
-                    if y > 0 {
-                        if self.cubes[x + CHUNK_USIZE * z + CHUNK_USIZE * CHUNK_USIZE * (y - 1)]
-                            .cube_type
-                            == CubeType::AIR
-                        {
-                            faces.push(Quad::new(
-                                Coord3DI::new(pos_x, pos_y, pos_z),
-                                Rotation::DOWN,
-                                texture_index[5],
-                            ));
-                        }
+                    let texture_index = unsafe { texture::TEXTURE_INDEX_LIST[cube_type as usize] };
+                    let pos =
+                        Coord3DF::new((x as i32 + world_pos.x) as f32, pos_y as f32, pos_z as f32);
+                    // Fluid surfaces are lowered by their level and animated in
+                    // the vertex shader.
+                    let fluid = cube_type.is_fluid();
+                    let flags = if fluid { texture::ANIMATED_BIT } else { 0 };
+                    let sides = [
+                        (Rotation::LEFT, texture_index[0]),
+                        (Rotation::RIGHT, texture_index[1]),
+                        (Rotation::BACK, texture_index[2]),
+                        (Rotation::FRONT, texture_index[3]),
+                        (Rotation::UP, texture_index[4]),
+                        (Rotation::DOWN, texture_index[5]),
+                    ];
+                    // Only the top cube of a fluid column is lowered.
+                    let height = if fluid && neighbour(x, y, z, Rotation::UP) != cube_type {
+                        self.cube(x, y, z).fluid_height()
                     } else {
-                        faces.push(Quad::new(
-                            Coord3DI::new(pos_x, pos_y, pos_z),
-                            Rotation::DOWN,
-                            texture_index[5],
-                        ));
-                    }
-                    if y < CHUNK_USIZE - 1 {
-                        if self.cubes[x + CHUNK_USIZE * z + CHUNK_USIZE * CHUNK_USIZE * (y + 1)]
-                            .cube_type
-                            == CubeType::AIR
-                        {
-                            faces.push(Quad::new(
-                                Coord3DI::new(pos_x, pos_y, pos_z),
-                                Rotation::UP,
-                                texture_index[4],
-                            ));
+                        1.
+                    };
+                    for (rotation, index) in sides.iter() {
+                        if cube_type.is_face_hidden(neighbour(x, y, z, *rotation)) {
+                            continue;
                         }
-                    } else {
-                        faces.push(Quad::new(
-                            Coord3DI::new(pos_x, pos_y, pos_z),
-                            Rotation::UP,
-                            texture_index[4],
-                        ));
+                        let quad = Quad::new(pos, *rotation, *index | flags, 1.);
+                        let quad = match rotation {
+                            Rotation::UP => quad.lowered(1. - height),
+                            Rotation::DOWN => quad,
+                            _ => quad.with_height(height),
+                        };
+                        faces.push(cube_type, quad);
                     }
                 }
             }
@@ -245,26 +243,103 @@ impl Chunk {
     }
 }
 
+/// Faces of a chunk split by the pass they are drawn in.
+pub struct Faces {
+    pub opaque: Vec<Quad>,
+    pub translucent: Vec<Quad>,
+}
+
+impl Faces {
+    fn new() -> Self {
+        Self {
+            opaque: Vec::new(),
+            translucent: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, cube_type: CubeType, quad: Quad) {
+        if cube_type.transparency() == Transparency::Translucent {
+            self.translucent.push(quad);
+        } else {
+            self.opaque.push(quad);
+        }
+    }
+}
+
 pub struct ChunkMesh {
-    instance_buffer: wgpu::Buffer,
-    instances_len: usize,
+    pub instance_buffer: wgpu::Buffer,
+    pub instances_len: usize,
+    translucent_buffer: wgpu::Buffer,
+    pub translucent_len: usize,
+    // Copy of the translucent faces, reordered when the camera moves.
+    translucent: Vec<InstanceRaw>,
+    // Cube the camera was in when the translucent faces were last sorted.
+    sorted_from: Option<Coord3DI>,
+    pub visibility: ChunkVisibility,
+    pub lod: u32,
 }
 
 impl ChunkMesh {
-    pub fn new(device: &wgpu::Device, faces: Vec<Quad>) -> Self {
-        let instance_data = faces.iter().map(Quad::to_raw).collect::<Vec<_>>();
+    pub fn new(device: &wgpu::Device, faces: Faces, visibility: ChunkVisibility, lod: u32) -> Self {
+        let instance_data = faces.opaque.iter().map(Quad::to_raw).collect::<Vec<_>>();
         let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: None,
             contents: bytemuck::cast_slice(instance_data.as_slice()),
-            usage: wgpu::BufferUsages::VERTEX,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_SRC,
+        });
+        let instances_len = faces.opaque.len();
+        let translucent_data = faces
+            .translucent
+            .iter()
+            .map(Quad::to_raw)
+            .collect::<Vec<_>>();
+        let translucent_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: None,
+            contents: bytemuck::cast_slice(translucent_data.as_slice()),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
         });
-        let instances_len = faces.len();
+        let translucent_len = faces.translucent.len();
         Self {
             instance_buffer,
             instances_len,
+            translucent_buffer,
+            translucent_len,
+            translucent: translucent_data,
+            sorted_from: None,
+            visibility,
+            lod,
         }
     }
 
+    /// Orders the translucent faces back to front as seen from `eye`, so
+    /// they blend correctly within the chunk. Only sorts again once the
+    /// camera enters another cube.
+    pub fn sort_translucent(&mut self, queue: &wgpu::Queue, eye: Coord3DF) {
+        let cube = Coord3DI::new(
+            eye.x.round() as i32,
+            eye.y.round() as i32,
+            eye.z.round() as i32,
+        );
+        if self.translucent_len == 0 || self.sorted_from == Some(cube) {
+            return;
+        }
+        self.sorted_from = Some(cube);
+        let distance = |i: &InstanceRaw| {
+            let center = i.t_matrix[3];
+            let dx = center[0] - eye.x;
+            let dy = center[1] - eye.y;
+            let dz = center[2] - eye.z;
+            dx * dx + dy * dy + dz * dz
+        };
+        self.translucent
+            .sort_by(|a, b| distance(b).partial_cmp(&distance(a)).unwrap());
+        queue.write_buffer(
+            &self.translucent_buffer,
+            0,
+            bytemuck::cast_slice(self.translucent.as_slice()),
+        );
+    }
+
     pub fn render<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, render_data: &'a RenderPassData) {
         pass.set_vertex_buffer(0, render_data.face_vertex_buffer.slice(..));
         pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
@@ -275,4 +350,35 @@ impl ChunkMesh {
         pass.set_bind_groups(&render_data);
         pass.draw_indexed(0..render_data.indices_len, 0, 0..self.instances_len as _);
     }
+
+    /// Draws the opaque faces without binding any uniforms, used for
+    /// depth only passes.
+    pub fn render_depth<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        render_data: &'a RenderPassData,
+    ) {
+        pass.set_vertex_buffer(0, render_data.face_vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        pass.set_index_buffer(
+            render_data.face_index_buffer.slice(..),
+            wgpu::IndexFormat::Uint32,
+        );
+        pass.draw_indexed(0..render_data.indices_len, 0, 0..self.instances_len as _);
+    }
+
+    pub fn render_translucent<'a>(
+        &'a self,
+        pass: &mut wgpu::RenderPass<'a>,
+        render_data: &'a RenderPassData,
+    ) {
+        pass.set_vertex_buffer(0, render_data.face_vertex_buffer.slice(..));
+        pass.set_vertex_buffer(1, self.translucent_buffer.slice(..));
+        pass.set_index_buffer(
+            render_data.face_index_buffer.slice(..),
+            wgpu::IndexFormat::Uint32,
+        );
+        pass.set_bind_groups(&render_data);
+        pass.draw_indexed(0..render_data.indices_len, 0, 0..self.translucent_len as _);
+    }
 }
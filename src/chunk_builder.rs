@@ -1,4 +1,5 @@
 use crate::chunk::{Chunk, ChunkMesh};
+use crate::chunk_cache::ChunkCache;
 use crate::coordinate::ChunkCoord3D;
 use crate::frustum_culling::Frustum;
 use crate::player::Player;
@@ -22,8 +23,19 @@ pub struct ChunkGenerator {
 
     // Chunks in loading process
     data_in_process: Vec<ChunkCoord3D>,
+
+    // Chunk the LOD levels are currently centered around.
+    lod_center: ChunkCoord3D,
+
+    // Recently unloaded chunk data
+    pub cache: ChunkCache,
 }
 
+// Number of unloaded chunks kept in memory.
+const CHUNK_CACHE_CAPACITY: usize = 512;
+// Fluids can change several chunks each tick.
+const MAX_REBUILDS_PER_TICK: usize = 4;
+
 impl ChunkGenerator {
     pub fn new() -> Self {
         let (data_sender, data_receiver) = flume::unbounded();
@@ -39,6 +51,8 @@ impl ChunkGenerator {
             chunk_load_queue,
             chunk_rebuild_queue,
             data_in_process,
+            lod_center: ChunkCoord3D::new(0, 0, 0),
+            cache: ChunkCache::new(CHUNK_CACHE_CAPACITY),
         }
     }
 
@@ -52,6 +66,8 @@ impl ChunkGenerator {
     ) {
         // Enqueue chunks within render distance.
         self.load_primary_chunks(&graphics, world, player, &pool);
+        // Rebuild chunks whose LOD level changed.
+        self.enqueue_lod_changes(world, &player);
         // Enqueue chunks in frustum.
         self.enqueue_chunks_in_frustum(world, &player, &frustum);
         // Load chunks in queue.
@@ -74,15 +90,7 @@ impl ChunkGenerator {
     ) {
         if !self.chunk_load_queue.is_empty() {
             let pos = self.chunk_load_queue.remove(0);
-            let adjacent_chunks = self.adjacent_chunks(pos, &world);
-            let sender = self.data_sender.clone();
-            let device = Arc::clone(&graphics.device);
-            pool.execute(move || {
-                let data = Arc::new(Chunk::new(pos));
-                let mesh = data.create_mesh(device.clone(), adjacent_chunks);
-                sender.send((data, mesh)).unwrap();
-            });
-            self.data_in_process.push(pos);
+            self.spawn_chunk_load(&graphics, pos, &world, &pool);
         }
     }
 
@@ -94,16 +102,20 @@ impl ChunkGenerator {
     ) {
         // Only rebuild when chunk load queue is empty.
         if self.data_in_process.is_empty() {
-            if !self.chunk_rebuild_queue.is_empty() {
+            for _ in 0..MAX_REBUILDS_PER_TICK {
+                if self.chunk_rebuild_queue.is_empty() {
+                    break;
+                }
                 let pos = self.chunk_rebuild_queue.remove(0);
                 // Only rebuild if the chunk queued for rebuild still exists.
                 if let Some(d) = world.chunks.get(&pos) {
                     let adjacent_chunks = self.adjacent_chunks(pos, &world);
+                    let lod = world::lod_level(self.lod_center, pos);
                     let data = d.clone();
                     let sender = self.mesh_sender.clone();
                     let device = Arc::clone(&graphics.device);
                     pool.execute(move || {
-                        let new_mesh = data.create_mesh(device, adjacent_chunks);
+                        let new_mesh = data.create_mesh(device, adjacent_chunks, lod);
                         sender.send((pos, new_mesh)).unwrap();
                     });
                 }
@@ -119,51 +131,50 @@ impl ChunkGenerator {
         pool: &ThreadPool,
     ) {
         if !self.is_chunk_loaded(&world, &pos) {
-            let adjacent_chunks = self.adjacent_chunks(pos, &world);
-            let sender = self.data_sender.clone();
-            let device = Arc::clone(&graphics.device);
-            pool.execute(move || {
-                let data = Arc::new(Chunk::new(pos));
-                let mesh = data.create_mesh(device.clone(), adjacent_chunks);
-                sender.send((data, mesh)).unwrap();
-            });
-            self.data_in_process.push(pos);
+            self.spawn_chunk_load(&graphics, pos, &world, &pool);
         }
     }
 
+    fn spawn_chunk_load(
+        &mut self,
+        graphics: &Graphics,
+        pos: ChunkCoord3D,
+        world: &World,
+        pool: &ThreadPool,
+    ) {
+        let adjacent_chunks = self.adjacent_chunks(pos, &world);
+        let lod = world::lod_level(self.lod_center, pos);
+        // Reuse the terrain if the chunk was unloaded recently.
+        let cached = self.cache.take(&pos);
+        let sender = self.data_sender.clone();
+        let device = Arc::clone(&graphics.device);
+        pool.execute(move || {
+            let data = cached.unwrap_or_else(|| Arc::new(Chunk::new(pos)));
+            let mesh = data.create_mesh(device.clone(), adjacent_chunks, lod);
+            sender.send((data, mesh)).unwrap();
+        });
+        self.data_in_process.push(pos);
+    }
+
     fn adjacent_chunks(&mut self, pos: ChunkCoord3D, world: &World) -> Vec<Option<Arc<Chunk>>> {
         let mut adjacent_chunks = Vec::new();
-        if let Some(c) = world
-            .chunks
-            .get(&ChunkCoord3D::new(pos.x - 1, pos.y, pos.z))
-        {
-            adjacent_chunks.push(Some(c.clone()));
-        } else {
-            adjacent_chunks.push(None);
-        }
-        if let Some(c) = world
-            .chunks
-            .get(&ChunkCoord3D::new(pos.x + 1, pos.y, pos.z))
-        {
-            adjacent_chunks.push(Some(c.clone()));
-        } else {
-            adjacent_chunks.push(None);
-        }
-        if let Some(c) = world
-            .chunks
-            .get(&ChunkCoord3D::new(pos.x, pos.y, pos.z - 1))
-        {
-            adjacent_chunks.push(Some(c.clone()));
-        } else {
-            adjacent_chunks.push(None);
-        }
-        if let Some(c) = world
-            .chunks
-            .get(&ChunkCoord3D::new(pos.x, pos.y, pos.z + 1))
-        {
-            adjacent_chunks.push(Some(c.clone()));
-        } else {
-            adjacent_chunks.push(None);
+        let lod = world::lod_level(self.lod_center, pos);
+        let positions = [
+            ChunkCoord3D::new(pos.x - 1, pos.y, pos.z),
+            ChunkCoord3D::new(pos.x + 1, pos.y, pos.z),
+            ChunkCoord3D::new(pos.x, pos.y, pos.z - 1),
+            ChunkCoord3D::new(pos.x, pos.y, pos.z + 1),
+        ];
+        for p in positions.iter() {
+            // Faces next to a chunk of another LOD level are never culled
+            // to avoid cracks between the two meshes.
+            if world::lod_level(self.lod_center, *p) != lod {
+                adjacent_chunks.push(None);
+            } else if let Some(c) = world.chunks.get(p) {
+                adjacent_chunks.push(Some(c.clone()));
+            } else {
+                adjacent_chunks.push(None);
+            }
         }
         adjacent_chunks
     }
@@ -214,18 +225,39 @@ impl ChunkGenerator {
         }
     }
 
+    fn enqueue_lod_changes(&mut self, world: &World, player: &Player) {
+        if self.lod_center == player.chunk {
+            return;
+        }
+        let old_center = self.lod_center;
+        self.lod_center = player.chunk;
+        for (p, m) in world.meshes.iter() {
+            let lod = world::lod_level(self.lod_center, *p);
+            if m.lod != lod {
+                self.enqueue_rebuild(*p);
+            }
+            // Neighbours of a chunk which changed its LOD level have to
+            // cull their border faces differently.
+            if lod != world::lod_level(old_center, *p) {
+                self.rebuild_adjacent_chunks(&world, p);
+            }
+        }
+    }
+
     fn enqueue_chunks_in_frustum(&mut self, world: &mut World, player: &Player, frustum: &Frustum) {
         let player_pos = player.chunk.clone();
-        if world::RENDER_DISTANCE > 1 {
-            for radius in 2..world::RENDER_DISTANCE {
+        if world.render_distance > 1 {
+            for radius in 2..world.render_distance + 1 {
                 for z in -radius..radius + 1 {
                     self.enqueue_data(
                         &world,
+                        player_pos,
                         ChunkCoord3D::new(player_pos.x + radius, 0, player_pos.z + z),
                         &frustum,
                     );
                     self.enqueue_data(
                         &world,
+                        player_pos,
                         ChunkCoord3D::new(player_pos.x - radius, 0, player_pos.z + z),
                         &frustum,
                     );
@@ -233,11 +265,13 @@ impl ChunkGenerator {
                 for x in (-radius + 1)..radius {
                     self.enqueue_data(
                         &world,
+                        player_pos,
                         ChunkCoord3D::new(player_pos.x + x, 0, player_pos.z + radius),
                         &frustum,
                     );
                     self.enqueue_data(
                         &world,
+                        player_pos,
                         ChunkCoord3D::new(player_pos.x + x, 0, player_pos.z - radius),
                         &frustum,
                     );
@@ -246,7 +280,17 @@ impl ChunkGenerator {
         }
     }
 
-    fn enqueue_data(&mut self, world: &World, pos: ChunkCoord3D, frustum: &Frustum) {
+    fn enqueue_data(
+        &mut self,
+        world: &World,
+        center: ChunkCoord3D,
+        pos: ChunkCoord3D,
+        frustum: &Frustum,
+    ) {
+        // Skip the corners of the square ring outside of the load radius.
+        if !world::is_within_radius(center, pos, world.render_distance) {
+            return;
+        }
         if !self.is_chunk_loaded(world, &pos) {
             if frustum.contains(&pos) {
                 self.chunk_load_queue.push(pos);
@@ -265,36 +309,46 @@ impl ChunkGenerator {
         return true;
     }
 
+    /// Whether no chunk is queued or being generated.
+    pub fn is_idle(&self) -> bool {
+        self.chunk_load_queue.is_empty()
+            && self.chunk_rebuild_queue.is_empty()
+            && self.data_in_process.is_empty()
+    }
+
     // Rebuilding the chunk
+    /// Queues a chunk for a rebuild unless it is already queued.
+    pub fn enqueue_rebuild(&mut self, pos: ChunkCoord3D) {
+        if !self.chunk_rebuild_queue.contains(&pos) {
+            self.chunk_rebuild_queue.push(pos);
+        }
+    }
+
     fn rebuild_adjacent_chunks(&mut self, world: &World, pos: &ChunkCoord3D) {
         if !world.chunks.is_empty() {
             if world
                 .chunks
                 .contains_key(&ChunkCoord3D::new(pos.x + 1, pos.y, pos.z))
             {
-                self.chunk_rebuild_queue
-                    .push(ChunkCoord3D::new(pos.x + 1, pos.y, pos.z));
+                self.enqueue_rebuild(ChunkCoord3D::new(pos.x + 1, pos.y, pos.z));
             }
             if world
                 .chunks
                 .contains_key(&ChunkCoord3D::new(pos.x - 1, pos.y, pos.z))
             {
-                self.chunk_rebuild_queue
-                    .push(ChunkCoord3D::new(pos.x - 1, pos.y, pos.z));
+                self.enqueue_rebuild(ChunkCoord3D::new(pos.x - 1, pos.y, pos.z));
             }
             if world
                 .chunks
                 .contains_key(&ChunkCoord3D::new(pos.x, pos.y, pos.z + 1))
             {
-                self.chunk_rebuild_queue
-                    .push(ChunkCoord3D::new(pos.x, pos.y, pos.z + 1));
+                self.enqueue_rebuild(ChunkCoord3D::new(pos.x, pos.y, pos.z + 1));
             }
             if world
                 .chunks
                 .contains_key(&ChunkCoord3D::new(pos.x, pos.y, pos.z - 1))
             {
-                self.chunk_rebuild_queue
-                    .push(ChunkCoord3D::new(pos.x, pos.y, pos.z - 1));
+                self.enqueue_rebuild(ChunkCoord3D::new(pos.x, pos.y, pos.z - 1));
             }
         }
     }
@@ -316,34 +370,33 @@ impl ChunkGenerator {
                     data.position.x, data.position.y, data.position.z
                 );
                 world.chunks.insert(pos, data);
-                world.meshes.insert(pos, mesh);
+                world.insert_mesh(pos, mesh);
                 self.data_in_process.retain(|&p| p != pos);
                 self.rebuild_adjacent_chunks(&world, &pos); // Used for rebuilding adjacent chunks, in other words culling the nearby chunks.
             }
             Err(_) => {}
         }
-        match self.mesh_receiver.try_recv() {
-            Ok((pos, mesh)) => {
-                println!("Rebuilt chunk at: x: {}, y: {}, z: {}", pos.x, pos.y, pos.z);
-                world.meshes.insert(pos, mesh);
-            }
-            Err(_) => {}
+        for (pos, mesh) in self.mesh_receiver.try_iter() {
+            println!("Rebuilt chunk at: x: {}, y: {}, z: {}", pos.x, pos.y, pos.z);
+            world.insert_mesh(pos, mesh);
         }
     }
 
     fn filter_unseen_chunks(&mut self, world: &mut World, player: &Player) {
+        // Chunks are unloaded further away than they are loaded so moving
+        // back and forth across a chunk border doesn't reload them.
+        let unload_radius = world.render_distance + world::UNLOAD_MARGIN;
         let meshes = &mut world.meshes;
-        world.chunks.retain(|p, _| {
-            if p.x <= world::RENDER_DISTANCE + player.chunk.x
-                && p.z <= world::RENDER_DISTANCE + player.chunk.z
-                && p.x >= -world::RENDER_DISTANCE + player.chunk.x
-                && p.z >= -world::RENDER_DISTANCE + player.chunk.z
-                && p.y <= world::RENDER_DISTANCE + player.chunk.y
-                && p.y >= -world::RENDER_DISTANCE + player.chunk.y
-            {
+        let indirect = &mut world.indirect;
+        world.chunks.retain(|p, c| {
+            if world::is_within_radius(player.chunk, *p, unload_radius) {
                 return true;
             }
+            self.cache.insert(*p, c.clone());
             meshes.remove(p);
+            if let Some(i) = indirect.as_mut() {
+                i.invalidate(*p);
+            }
             if let Some(i) = self.chunk_rebuild_queue.iter().position(|&pos| pos == *p) {
                 self.chunk_rebuild_queue.remove(i);
             }
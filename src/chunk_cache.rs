@@ -0,0 +1,137 @@
+use crate::chunk::Chunk;
+use crate::coordinate::ChunkCoord3D;
+use hashbrown::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+/// Keeps the data of recently unloaded chunks so walking back into an area
+/// doesn't have to generate the terrain again. The least recently unloaded
+/// chunk is evicted once the capacity is reached.
+pub struct ChunkCache {
+    chunks: HashMap<ChunkCoord3D, (Arc<Chunk>, u64)>,
+    // Positions in insertion order with the time they were inserted at.
+    // Entries taken or inserted again since are skipped when evicting.
+    order: VecDeque<(ChunkCoord3D, u64)>,
+    capacity: usize,
+    time: u64,
+}
+
+impl ChunkCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            chunks: HashMap::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+            time: 0,
+        }
+    }
+
+    pub fn insert(&mut self, pos: ChunkCoord3D, chunk: Arc<Chunk>) {
+        self.time += 1;
+        self.chunks.insert(pos, (chunk, self.time));
+        self.order.push_back((pos, self.time));
+        while self.chunks.len() > self.capacity {
+            let (p, time) = match self.order.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            if self.is_current(&p, time) {
+                self.chunks.remove(&p);
+            }
+        }
+        // Taking chunks leaves stale entries behind without evicting any.
+        if self.order.len() > 2 * self.capacity {
+            let order = std::mem::take(&mut self.order);
+            self.order = order
+                .into_iter()
+                .filter(|(p, time)| self.is_current(p, *time))
+                .collect();
+        }
+    }
+
+    /// Removes the chunk from the cache and returns it.
+    pub fn take(&mut self, pos: &ChunkCoord3D) -> Option<Arc<Chunk>> {
+        self.chunks.remove(pos).map(|(chunk, _)| chunk)
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    // Whether the entry in `order` still refers to the cached chunk.
+    fn is_current(&self, pos: &ChunkCoord3D, time: u64) -> bool {
+        self.chunks.get(pos).map_or(false, |(_, t)| *t == time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(x: i32) -> (ChunkCoord3D, Arc<Chunk>) {
+        let pos = ChunkCoord3D::new(x, 0, 0);
+        (pos, Arc::new(Chunk::empty(pos)))
+    }
+
+    fn insert(cache: &mut ChunkCache, x: i32) {
+        let (pos, chunk) = chunk(x);
+        cache.insert(pos, chunk);
+    }
+
+    fn contains(cache: &ChunkCache, x: i32) -> bool {
+        cache.chunks.contains_key(&ChunkCoord3D::new(x, 0, 0))
+    }
+
+    #[test]
+    fn evicts_the_oldest_chunk() {
+        let mut cache = ChunkCache::new(3);
+        for x in 0..5 {
+            insert(&mut cache, x);
+        }
+        assert_eq!(cache.len(), 3);
+        assert!(!contains(&cache, 0) && !contains(&cache, 1));
+        assert!(contains(&cache, 2) && contains(&cache, 3) && contains(&cache, 4));
+    }
+
+    #[test]
+    fn take_removes_the_chunk() {
+        let mut cache = ChunkCache::new(3);
+        insert(&mut cache, 0);
+        let pos = ChunkCoord3D::new(0, 0, 0);
+        assert_eq!(cache.take(&pos).unwrap().position, pos);
+        assert!(cache.take(&pos).is_none());
+        assert_eq!(cache.len(), 0);
+    }
+
+    #[test]
+    fn taken_chunks_free_their_space() {
+        let mut cache = ChunkCache::new(2);
+        insert(&mut cache, 0);
+        insert(&mut cache, 1);
+        cache.take(&ChunkCoord3D::new(0, 0, 0));
+        insert(&mut cache, 2);
+        assert!(contains(&cache, 1) && contains(&cache, 2));
+    }
+
+    #[test]
+    fn inserting_again_makes_a_chunk_the_newest() {
+        let mut cache = ChunkCache::new(2);
+        insert(&mut cache, 0);
+        insert(&mut cache, 1);
+        insert(&mut cache, 0);
+        assert_eq!(cache.len(), 2);
+        insert(&mut cache, 2);
+        assert!(contains(&cache, 0) && contains(&cache, 2));
+        assert!(!contains(&cache, 1));
+    }
+
+    #[test]
+    fn stale_entries_are_dropped() {
+        let mut cache = ChunkCache::new(2);
+        for _ in 0..10 {
+            insert(&mut cache, 0);
+            cache.take(&ChunkCoord3D::new(0, 0, 0));
+        }
+        assert!(cache.order.len() <= 4);
+    }
+}
@@ -0,0 +1,107 @@
+use nalgebra::Vector3;
+
+// Ticks in one in-game day.
+pub const DAY_TICKS: u32 = 2400;
+pub const HOUR_TICKS: u32 = DAY_TICKS / 24;
+
+const DAY_HORIZON: [f32; 3] = [0.62, 0.75, 0.88];
+const DAY_ZENITH: [f32; 3] = [0.18, 0.38, 0.72];
+const SUNSET_HORIZON: [f32; 3] = [0.9, 0.5, 0.3];
+const NIGHT_HORIZON: [f32; 3] = [0.03, 0.04, 0.08];
+const NIGHT_ZENITH: [f32; 3] = [0.005, 0.01, 0.03];
+
+// Light which is left at midnight.
+const MIN_AMBIENT: f32 = 0.15;
+
+/// Light and sky colors for the current time of day.
+pub struct Lighting {
+    pub sun_direction: Vector3<f32>,
+    pub horizon_color: [f32; 3],
+    pub zenith_color: [f32; 3],
+    // Light reaching every face regardless of its direction.
+    pub ambient: f32,
+    // Intensity of the light coming from the sun or moon.
+    pub skylight: f32,
+}
+
+/// World time advanced by the engine's tick system. Tick 0 is sunrise,
+/// a quarter day later the sun is at its highest.
+pub struct WorldClock {
+    ticks: u32,
+    pub frozen: bool,
+}
+
+impl WorldClock {
+    pub fn new() -> Self {
+        Self {
+            // Start in the morning.
+            ticks: 2 * HOUR_TICKS,
+            frozen: false,
+        }
+    }
+
+    pub fn tick(&mut self) {
+        if !self.frozen {
+            self.ticks = (self.ticks + 1) % DAY_TICKS;
+        }
+    }
+
+    pub fn set_time(&mut self, ticks: u32) {
+        self.ticks = ticks % DAY_TICKS;
+    }
+
+    /// Moves the time by whole hours, also while frozen.
+    pub fn skip_hours(&mut self, hours: i32) {
+        let ticks = self.ticks as i32 + hours * HOUR_TICKS as i32;
+        self.set_time(ticks.rem_euclid(DAY_TICKS as i32) as u32);
+    }
+
+    /// Sets the time to a full hour on the 24 hour clock.
+    pub fn set_hour(&mut self, hour: u32) {
+        self.set_time((hour + 18) % 24 * HOUR_TICKS);
+    }
+
+    /// Hours and minutes on a 24 hour clock, sunrise is at 6:00.
+    pub fn time_of_day(&self) -> (u32, u32) {
+        let ticks = (self.ticks + 6 * HOUR_TICKS) % DAY_TICKS;
+        (ticks / HOUR_TICKS, ticks % HOUR_TICKS * 60 / HOUR_TICKS)
+    }
+
+    pub fn lighting(&self) -> Lighting {
+        let angle = self.ticks as f32 / DAY_TICKS as f32 * std::f32::consts::PI * 2.;
+        // The sun rises in the east (+x) and its path is tilted to the south.
+        let sun_direction = Vector3::new(angle.cos(), angle.sin(), 0.3).normalize();
+        let height = sun_direction.y;
+
+        // 0 at night, 1 during the day with a short twilight in between.
+        let day = smoothstep(-0.15, 0.2, height);
+        // Strongest while the sun is close to the horizon.
+        let sunset = (1. - (height.abs() / 0.25).min(1.)) * smoothstep(-0.2, 0., height);
+
+        let horizon = mix(
+            mix(NIGHT_HORIZON, DAY_HORIZON, day),
+            SUNSET_HORIZON,
+            sunset * 0.6,
+        );
+        Lighting {
+            sun_direction,
+            horizon_color: horizon,
+            zenith_color: mix(NIGHT_ZENITH, DAY_ZENITH, day),
+            ambient: MIN_AMBIENT + (0.4 - MIN_AMBIENT) * day,
+            skylight: day * 0.6,
+        }
+    }
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).max(0.).min(1.);
+    t * t * (3. - 2. * t)
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ]
+}
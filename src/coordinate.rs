@@ -1,4 +1,4 @@
-#[derive(Clone, Copy, Debug)]
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Debug)]
 pub struct Coord3DI {
     pub x: i32,
     pub y: i32,
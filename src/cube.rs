@@ -1,11 +1,31 @@
-#[derive(Debug)]
+#[derive(Copy, Clone, Debug)]
 pub struct Cube {
     pub cube_type: CubeType,
+    // Fluid level, only used by fluid cubes.
+    pub level: u8,
 }
 
+/// Level of a fluid source. Flowing fluid loses levels as it spreads
+/// and disappears once it reaches 0.
+pub const FLUID_SOURCE_LEVEL: u8 = 8;
+
 impl Cube {
     pub fn new(cube_type: CubeType) -> Self {
-        Self { cube_type }
+        let level = if cube_type.is_fluid() {
+            FLUID_SOURCE_LEVEL
+        } else {
+            0
+        };
+        Self { cube_type, level }
+    }
+
+    pub fn fluid(cube_type: CubeType, level: u8) -> Self {
+        Self { cube_type, level }
+    }
+
+    /// Height of the fluid surface relative to a full cube.
+    pub fn fluid_height(&self) -> f32 {
+        self.level as f32 / (FLUID_SOURCE_LEVEL + 1) as f32
     }
 
     pub fn set_type(&mut self, cube_type: CubeType) {
@@ -17,5 +37,131 @@ impl Cube {
 pub enum CubeType {
     GRASS = 0,
     DIRT = 1,
+    LEAVES = 2,
+    GLASS = 3,
+    WATER = 4,
+    LAVA = 5,
     AIR,
 }
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Transparency {
+    // Not drawn at all.
+    Empty,
+    Opaque,
+    // Fully transparent texels are discarded, the rest is drawn as opaque.
+    Cutout,
+    // Blended and drawn in a separate sorted pass.
+    Translucent,
+}
+
+impl CubeType {
+    pub fn transparency(self) -> Transparency {
+        match self {
+            CubeType::GRASS | CubeType::DIRT | CubeType::LAVA => Transparency::Opaque,
+            CubeType::LEAVES => Transparency::Cutout,
+            CubeType::GLASS | CubeType::WATER => Transparency::Translucent,
+            CubeType::AIR => Transparency::Empty,
+        }
+    }
+
+    pub fn is_fluid(self) -> bool {
+        self == CubeType::WATER || self == CubeType::LAVA
+    }
+
+    /// Levels lost per cube a fluid flows sideways.
+    pub fn fluid_decay(self) -> u8 {
+        match self {
+            CubeType::LAVA => 2,
+            _ => 1,
+        }
+    }
+
+    /// Number of simulation ticks between two updates of a fluid.
+    pub fn fluid_tick_rate(self) -> u32 {
+        match self {
+            CubeType::LAVA => 3,
+            _ => 1,
+        }
+    }
+
+    /// Whether bodies collide with the cube, fluids can be moved through.
+    pub fn is_solid(self) -> bool {
+        self != CubeType::AIR && !self.is_fluid()
+    }
+
+    pub fn is_opaque(self) -> bool {
+        self.transparency() == Transparency::Opaque
+    }
+
+    /// Whether the face of this cube towards `neighbour` can't be seen.
+    /// Faces between two different transparent cubes are still drawn,
+    /// but not between two translucent cubes of the same type. Fluids
+    /// don't fill their whole cube, so they only hide faces of the same
+    /// fluid.
+    pub fn is_face_hidden(self, neighbour: CubeType) -> bool {
+        if neighbour.is_fluid() {
+            return neighbour == self;
+        }
+        match neighbour.transparency() {
+            Transparency::Empty | Transparency::Cutout => false,
+            Transparency::Opaque => true,
+            Transparency::Translucent => neighbour == self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn air_has_no_transparency_pass() {
+        assert_eq!(CubeType::AIR.transparency(), Transparency::Empty);
+        assert!(!CubeType::AIR.is_opaque());
+    }
+
+    #[test]
+    fn opaque_neighbours_hide_every_face() {
+        for cube in [
+            CubeType::DIRT,
+            CubeType::LEAVES,
+            CubeType::GLASS,
+            CubeType::WATER,
+        ] {
+            assert!(cube.is_face_hidden(CubeType::GRASS));
+            assert!(cube.is_face_hidden(CubeType::DIRT));
+        }
+    }
+
+    #[test]
+    fn air_and_cutout_neighbours_hide_nothing() {
+        for cube in [CubeType::DIRT, CubeType::LEAVES, CubeType::GLASS] {
+            assert!(!cube.is_face_hidden(CubeType::AIR));
+            assert!(!cube.is_face_hidden(CubeType::LEAVES));
+        }
+    }
+
+    #[test]
+    fn faces_between_different_transparent_cubes_are_drawn() {
+        assert!(!CubeType::GLASS.is_face_hidden(CubeType::WATER));
+        assert!(!CubeType::WATER.is_face_hidden(CubeType::GLASS));
+        assert!(!CubeType::LEAVES.is_face_hidden(CubeType::GLASS));
+        assert!(!CubeType::GLASS.is_face_hidden(CubeType::LEAVES));
+        assert!(!CubeType::DIRT.is_face_hidden(CubeType::GLASS));
+    }
+
+    #[test]
+    fn faces_between_the_same_translucent_cubes_are_hidden() {
+        assert!(CubeType::GLASS.is_face_hidden(CubeType::GLASS));
+        assert!(CubeType::WATER.is_face_hidden(CubeType::WATER));
+    }
+
+    #[test]
+    fn fluids_only_hide_faces_of_the_same_fluid() {
+        assert!(!CubeType::DIRT.is_face_hidden(CubeType::WATER));
+        assert!(!CubeType::LAVA.is_face_hidden(CubeType::WATER));
+        assert!(!CubeType::WATER.is_face_hidden(CubeType::LAVA));
+        assert!(CubeType::LAVA.is_face_hidden(CubeType::LAVA));
+    }
+}
@@ -1,9 +1,11 @@
 use crate::camera::Camera;
 use crate::chunk_builder::ChunkGenerator;
 use crate::coordinate::Coord3DF;
+use crate::cube::CubeType;
 use crate::renderer::graphics::Graphics;
 use crate::world::World;
 use futures::task::SpawnExt;
+use std::collections::BTreeMap;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use wgpu_glyph::{ab_glyph, GlyphBrushBuilder, Section, Text};
 
@@ -50,8 +52,13 @@ impl DebugInfoBuilder {
                 DebugTools::Chunk,
                 DebugTools::LoadedChunks,
                 DebugTools::RebuildQueue,
+                DebugTools::DrawnChunks,
+                DebugTools::Time,
+                DebugTools::PlacedCube,
             ],
             fps: 0.,
+            placed_cube: CubeType::DIRT,
+            reload_errors: BTreeMap::new(),
             staging_belt,
             local_pool,
             local_spawner,
@@ -70,6 +77,11 @@ pub struct DebugInfo {
     brush: wgpu_glyph::GlyphBrush<()>,
     text: Vec<DebugTools>,
     fps: f64,
+    /// Cube the player places.
+    pub placed_cube: CubeType,
+    /// Errors of failed hot reloads by their source, a shader file name,
+    /// `textures` or `bindings`. Cleared once that source reloads.
+    pub reload_errors: BTreeMap<String, String>,
     staging_belt: wgpu::util::StagingBelt,
     local_pool: futures::executor::LocalPool,
     local_spawner: futures::executor::LocalSpawner,
@@ -95,11 +107,34 @@ impl DebugInfo {
             "Chunk: x: {}, y: {}, z: {}\n",
             coords.x, coords.y, coords.z
         ));
-        let chunks_loaded_num = String::from(format!("Chunks loaded: {}\n", world.chunks.len()));
+        let chunks_loaded_num = String::from(format!(
+            "Chunks loaded: {} (render distance: {})\n",
+            world.chunks.len(),
+            world.render_distance
+        ));
         let chunk_queue = String::from(format!(
-            "Rebuild queue: {}\n",
-            chunk_gen.chunk_rebuild_queue.len()
+            "Rebuild queue: {}, cached chunks: {}\n",
+            chunk_gen.chunk_rebuild_queue.len(),
+            chunk_gen.cache.len()
+        ));
+        let drawn_chunks = String::from(format!(
+            "Chunks drawn: {} (occlusion culling: {})\n",
+            world.drawn_chunks,
+            if world.occlusion_culling { "on" } else { "off" }
+        ));
+        let (hours, minutes) = world.clock.time_of_day();
+        let time = String::from(format!(
+            "Time: {:02}:{:02}{}\n",
+            hours,
+            minutes,
+            if world.clock.frozen { " (frozen)" } else { "" }
         ));
+        let placed_cube = String::from(format!("Placing: {:?}\n", self.placed_cube));
+        let reload_errors = self
+            .reload_errors
+            .values()
+            .map(|e| format!("Reload failed: {}\n", e))
+            .collect::<String>();
         let mut debug_text: Vec<Text> = Vec::new();
         for t in self.text.iter() {
             match t {
@@ -138,8 +173,36 @@ impl DebugInfo {
                             .with_scale(self.scale),
                     );
                 }
+                DebugTools::DrawnChunks => {
+                    debug_text.push(
+                        Text::new(&drawn_chunks)
+                            .with_color([1., 1., 1., 1.])
+                            .with_scale(self.scale),
+                    );
+                }
+                DebugTools::Time => {
+                    debug_text.push(
+                        Text::new(&time)
+                            .with_color([1., 1., 1., 1.])
+                            .with_scale(self.scale),
+                    );
+                }
+                DebugTools::PlacedCube => {
+                    debug_text.push(
+                        Text::new(&placed_cube)
+                            .with_color([1., 1., 1., 1.])
+                            .with_scale(self.scale),
+                    );
+                }
             }
         }
+        if !reload_errors.is_empty() {
+            debug_text.push(
+                Text::new(&reload_errors)
+                    .with_color([1., 0.3, 0.3, 1.])
+                    .with_scale(self.scale),
+            );
+        }
         self.brush.queue(Section {
             screen_position: (self.position.0, self.position.1),
             bounds: (self.screen_bounds.0 as f32, self.screen_bounds.1 as f32),
@@ -189,4 +252,7 @@ pub enum DebugTools {
     Chunk,
     LoadedChunks,
     RebuildQueue,
+    DrawnChunks,
+    Time,
+    PlacedCube,
 }
@@ -1,37 +1,80 @@
+use crate::assets::Assets;
 use crate::camera::Camera;
 use crate::chunk_builder::ChunkGenerator;
+use crate::coordinate::{Coord3DF, Coord3DI};
+use crate::cube::{Cube, CubeType};
 use crate::debug_info::{DebugInfo, DebugInfoBuilder};
+use crate::fluid::FluidSimulation;
 use crate::frustum_culling::Frustum;
+use crate::hot_reload::{Change, FileWatcher};
+use crate::input::{Action, InputState};
+use crate::physics;
 use crate::player::Player;
 use crate::renderer::graphics::Graphics;
-use crate::renderer::renderer::Renderer;
+use crate::renderer::readback::PendingReadback;
+use crate::renderer::renderer::{Capture, Renderer};
 use crate::uniform::RenderPassData;
 use crate::world::World;
+use anyhow::Context;
+use nalgebra::Point3;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 pub struct Engine {
     renderer: Renderer,
     world: World,
     chunk_gen: ChunkGenerator,
+    fluids: FluidSimulation,
+    assets: Assets,
+    watcher: FileWatcher,
     uniforms: RenderPassData,
+    pub input: InputState,
     camera: Camera,
     player: Player,
     debug_info: DebugInfo,
+    pub show_debug_info: bool,
+    screenshot_request: Option<Capture>,
+    placed_cube: CubeType,
+    screenshots: Vec<PendingReadback>,
     tick_time: u32,
+    last_update: Instant,
+    // Frame time not simulated yet, less than one `STEP`.
+    accumulator: Duration,
     frustum: Frustum,
 }
 
+// Length of one simulation step, the simulation runs at the same rate
+// independent of the frame rate.
+const STEP: Duration = Duration::from_micros(16_667);
+// Simulation steps between two world updates.
 const TICK: u32 = 7;
+// Distance in front of the camera fluids are placed at.
+const PLACE_DISTANCE: f32 = 4.;
+// Distance up to which cubes can be broken and placed.
+const REACH: f32 = 5.;
+// Cubes `place_cube` cycles through, in order.
+const PLACEABLE_CUBES: [CubeType; 4] = [
+    CubeType::DIRT,
+    CubeType::GRASS,
+    CubeType::LEAVES,
+    CubeType::GLASS,
+];
+const SCREENSHOT_DIR: &str = "screenshots";
+// Longer frames are simulated as if they took this long, so a hitch
+// doesn't run lots of steps at once.
+const MAX_FRAME_TIME: Duration = Duration::from_millis(100);
 
 impl Engine {
-    pub fn new(graphics: &Graphics) -> Self {
-        let camera = Camera::new(&graphics);
-        let uniforms = RenderPassData::new(&graphics, &camera);
+    pub fn new(graphics: &Graphics, mut assets: Assets) -> Self {
+        let world = World::new(&graphics);
+        let mut camera = Camera::new(&graphics);
+        camera.set_far(world.view_distance());
+        let uniforms = RenderPassData::new(&graphics, &mut assets);
         let renderer = Renderer::new(&graphics, &uniforms);
-        let world = World::new();
         let chunk_gen = ChunkGenerator::new();
         unsafe { crate::texture::init_index_list() };
         let player = Player::new(&camera);
-        let debug_info = DebugInfoBuilder::new(
+        let mut debug_info = DebugInfoBuilder::new(
             10.,
             10.,
             30.,
@@ -40,26 +83,71 @@ impl Engine {
         )
         .build(&graphics)
         .unwrap();
+        debug_info.placed_cube = PLACEABLE_CUBES[0];
         let frustum = Frustum::new(&camera);
+        let input = InputState::new();
         Self {
             renderer,
             world,
             chunk_gen,
+            fluids: FluidSimulation::new(),
+            watcher: FileWatcher::new(
+                FileWatcher::shader_dir_from_args(),
+                assets.root().to_path_buf(),
+                input.bindings_path().to_path_buf(),
+            ),
+            assets,
             uniforms,
+            input,
             camera,
             player,
             debug_info,
+            show_debug_info: true,
+            screenshot_request: None,
+            placed_cube: PLACEABLE_CUBES[0],
+            screenshots: Vec::new(),
             tick_time: 0,
+            last_update: Instant::now(),
+            accumulator: Duration::ZERO,
             frustum,
         }
     }
 
     pub fn update(&mut self, graphics: &Graphics, pool: &uvth::ThreadPool) {
+        let now = Instant::now();
+        let frame_time = (now - self.last_update).min(MAX_FRAME_TIME);
+        self.accumulator += frame_time;
+        self.last_update = now;
+        self.camera
+            .controller
+            .apply_actions(&self.input, frame_time.as_secs_f32());
+        while self.accumulator >= STEP {
+            self.accumulator -= STEP;
+            self.step(&graphics, &pool);
+        }
+
+        // The camera is placed between the last two steps, so movement
+        // stays smooth when frames and steps don't line up.
+        let alpha = self.accumulator.as_secs_f32() / STEP.as_secs_f32();
         self.camera.update();
-        self.uniforms.update(&self.camera, &graphics);
-        self.player.update_pos(&self.camera);
+        self.camera
+            .follow(&self.world, self.player.eye(alpha), alpha);
+        self.uniforms.update(
+            &self.camera,
+            &graphics,
+            self.world.fog_distance(),
+            &self.world.clock.lighting(),
+        );
         unsafe { self.debug_info.update_info() };
         self.frustum.update(&self.camera);
+        self.hot_reload(&graphics);
+    }
+
+    // Advances the player and the world by one `STEP`.
+    fn step(&mut self, graphics: &Graphics, pool: &uvth::ThreadPool) {
+        self.camera.step(STEP.as_secs_f32());
+        self.player
+            .step(&self.world, &self.camera, STEP.as_secs_f32());
 
         // Tick system:
         self.tick_time += 1;
@@ -71,23 +159,100 @@ impl Engine {
                 &graphics,
                 &self.frustum,
             );
+            self.fluids.update(&mut self.world, &mut self.chunk_gen);
+            self.world.clock.tick();
             self.tick_time = 0;
         }
     }
 
+    // Applies changed shaders, textures and bindings. Failures keep the
+    // previous resources and show up in the debug overlay until the same
+    // source reloads successfully.
+    fn hot_reload(&mut self, graphics: &Graphics) {
+        for change in self.watcher.poll() {
+            let (name, result) = match change {
+                Change::Shader(file_name, source) => {
+                    let result =
+                        self.renderer
+                            .reload_shader(&graphics, &self.uniforms, &file_name, source);
+                    (file_name, result)
+                }
+                Change::Textures => (
+                    String::from("textures"),
+                    self.uniforms.reload_textures(&graphics, &mut self.assets),
+                ),
+                Change::Bindings => (String::from("bindings"), self.input.reload_bindings()),
+            };
+            match result {
+                Ok(()) => {
+                    self.debug_info.reload_errors.remove(&name);
+                }
+                Err(e) => {
+                    self.debug_info.reload_errors.insert(name, e);
+                }
+            }
+        }
+    }
+
     pub fn render(&mut self, graphics: &Graphics) -> Result<(), wgpu::SurfaceError> {
-        self.renderer.render(
+        self.save_screenshots(&graphics);
+        let eye = self.camera.eye;
+        self.world.prepare_render(
+            &graphics,
+            &self.frustum,
+            &self.uniforms,
+            Coord3DF::new(eye.x, eye.y, eye.z),
+        );
+        if self.show_debug_info {
+            self.world.count_drawn_chunks(&self.frustum);
+        }
+        let readback = self.renderer.render(
             &graphics,
             &self.world,
             &self.uniforms,
-            &mut self.debug_info,
+            if self.show_debug_info {
+                Some(&mut self.debug_info)
+            } else {
+                None
+            },
             &self.camera,
             &self.frustum,
             &self.chunk_gen,
+            self.screenshot_request.take(),
         )?;
+        self.screenshots.extend(readback);
         Ok(())
     }
 
+    /// Captures the next frame into a PNG in the `screenshots` directory.
+    pub fn take_screenshot(&mut self, capture: Capture) {
+        self.screenshot_request = Some(capture);
+    }
+
+    // Writes the screenshots whose frames have been read back, encoding
+    // happens on a separate thread.
+    fn save_screenshots(&mut self, graphics: &Graphics) {
+        if self.screenshots.is_empty() {
+            return;
+        }
+        graphics.device.poll(wgpu::Maintain::Poll);
+        let mut pending = Vec::new();
+        for mut screenshot in self.screenshots.drain(..) {
+            match screenshot.try_finish() {
+                None => pending.push(screenshot),
+                Some(Ok(image)) => {
+                    std::thread::spawn(move || {
+                        if let Err(e) = save_screenshot(image) {
+                            eprintln!("{:#}", e);
+                        }
+                    });
+                }
+                Some(Err(e)) => eprintln!("{:#}", e),
+            }
+        }
+        self.screenshots = pending;
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>, graphics: &mut Graphics) {
         graphics.resize(new_size);
         self.camera.resize(&graphics);
@@ -96,7 +261,191 @@ impl Engine {
         self.frustum.update(&self.camera);
     }
 
+    pub fn change_render_distance(&mut self, delta: i32) {
+        self.world
+            .set_render_distance(self.world.render_distance + delta);
+        self.camera.set_far(self.world.view_distance());
+        self.frustum.update(&self.camera);
+    }
+
+    /// Places a fluid source a few cubes in front of the camera.
+    pub fn place_fluid(&mut self, cube_type: CubeType) {
+        let pos = self.player.eye(1.) + self.camera.target.coords * PLACE_DISTANCE;
+        let pos = Coord3DI::new(
+            pos.x.round() as i32,
+            pos.y.round() as i32,
+            pos.z.round() as i32,
+        );
+        self.fluids
+            .place_source(&mut self.world, &mut self.chunk_gen, pos, cube_type);
+    }
+
+    /// Removes the cube the camera looks at.
+    pub fn break_cube(&mut self) {
+        if let Some((pos, _)) = self.target_cube() {
+            self.fluids.set_cube(
+                &mut self.world,
+                &mut self.chunk_gen,
+                pos,
+                Cube::new(CubeType::AIR),
+            );
+        }
+    }
+
+    /// Places a cube in front of the face the camera looks at, unless the
+    /// player is in the way.
+    pub fn place_cube(&mut self) {
+        if let Some((_, pos)) = self.target_cube() {
+            if !self.player.collides_with(pos) {
+                self.fluids.set_cube(
+                    &mut self.world,
+                    &mut self.chunk_gen,
+                    pos,
+                    Cube::new(self.placed_cube),
+                );
+            }
+        }
+    }
+
+    /// Switches to the next cube `place_cube` puts down.
+    pub fn cycle_placed_cube(&mut self) {
+        let next = PLACEABLE_CUBES
+            .iter()
+            .position(|c| *c == self.placed_cube)
+            .map_or(0, |i| (i + 1) % PLACEABLE_CUBES.len());
+        self.placed_cube = PLACEABLE_CUBES[next];
+        self.debug_info.placed_cube = self.placed_cube;
+    }
+
+    // First solid cube along the view within reach and the cube in front
+    // of it.
+    fn target_cube(&self) -> Option<(Coord3DI, Coord3DI)> {
+        // Measured from the player, the camera may be behind it.
+        let hit = physics::raycast(
+            &self.world,
+            self.player.eye(1.),
+            self.camera.target.coords,
+            REACH,
+        )?;
+        // Can't reach into chunks that aren't loaded.
+        self.world.cube_at(hit.cube)?;
+        Some((hit.cube, hit.previous))
+    }
+
+    /// Switches the player between walking and flying through terrain.
+    pub fn toggle_walking(&mut self) {
+        self.player.toggle_mode();
+    }
+
+    pub fn toggle_time_frozen(&mut self) {
+        self.world.clock.frozen = !self.world.clock.frozen;
+    }
+
+    pub fn set_time_frozen(&mut self, frozen: bool) {
+        self.world.clock.frozen = frozen;
+    }
+
+    /// Moves the camera to `eye`, looking along `yaw` and `pitch` in
+    /// degrees.
+    pub fn place_camera(&mut self, eye: Point3<f32>, yaw: f32, pitch: f32) {
+        self.camera.eye = eye;
+        self.camera.controller.yaw = yaw;
+        self.camera.controller.pitch = pitch;
+        self.camera.update();
+        self.player.teleport(eye);
+        self.frustum.update(&self.camera);
+    }
+
+    /// Whether chunks are loaded and no chunk is waiting to be generated
+    /// or rebuilt.
+    pub fn chunks_loaded(&self) -> bool {
+        !self.world.meshes.is_empty() && self.chunk_gen.is_idle()
+    }
+
+    /// Renders a frame without the debug overlay and reads it back, only
+    /// works with headless graphics.
+    pub fn screenshot(&mut self, graphics: &Graphics) -> anyhow::Result<image::RgbaImage> {
+        let show_debug_info = self.show_debug_info;
+        self.show_debug_info = false;
+        let result = self.render(&graphics);
+        self.show_debug_info = show_debug_info;
+        result.map_err(|e| anyhow::anyhow!("Couldn't render the screenshot: {:?}", e))?;
+        graphics.read_frame()
+    }
+
+    pub fn set_hour(&mut self, hour: u32) {
+        self.world.clock.set_hour(hour);
+    }
+
+    pub fn skip_hours(&mut self, hours: i32) {
+        self.world.clock.skip_hours(hours);
+    }
+
+    /// Switches to the next MSAA sample count.
+    pub fn cycle_msaa(&mut self, graphics: &Graphics) {
+        let sample_counts = self.renderer.sample_counts();
+        let current = sample_counts
+            .iter()
+            .position(|&c| c == self.renderer.sample_count())
+            .unwrap_or(0);
+        let sample_count = sample_counts[(current + 1) % sample_counts.len()];
+        self.renderer
+            .set_sample_count(&graphics, &self.uniforms, sample_count);
+    }
+
+    pub fn cycle_debug_view(&mut self) {
+        self.renderer.cycle_view();
+    }
+
+    pub fn toggle_occlusion_culling(&mut self) {
+        self.world.occlusion_culling = !self.world.occlusion_culling;
+    }
+
+    /// Performs an action triggered by the input, besides the ones that
+    /// concern the window.
+    pub fn perform(&mut self, action: Action, graphics: &Graphics) {
+        match action {
+            Action::Break => self.break_cube(),
+            Action::Place => self.place_cube(),
+            Action::CycleCube => self.cycle_placed_cube(),
+            Action::PlaceWater => self.place_fluid(CubeType::WATER),
+            Action::PlaceLava => self.place_fluid(CubeType::LAVA),
+            Action::ToggleDebug => self.show_debug_info = !self.show_debug_info,
+            Action::ToggleWalking => self.toggle_walking(),
+            Action::CycleCameraMode => self.camera.cycle_mode(self.player.eye(1.)),
+            Action::ToggleViewBobbing => self.player.view_bobbing = !self.player.view_bobbing,
+            Action::ToggleOcclusionCulling => self.toggle_occlusion_culling(),
+            Action::CycleDebugView => self.cycle_debug_view(),
+            Action::CycleMsaa => self.cycle_msaa(&graphics),
+            Action::RenderDistanceUp => self.change_render_distance(1),
+            Action::RenderDistanceDown => self.change_render_distance(-1),
+            Action::ToggleTime => self.toggle_time_frozen(),
+            Action::HourBack => self.skip_hours(-1),
+            Action::HourForward => self.skip_hours(1),
+            Action::SetNoon => self.set_hour(12),
+            Action::SetMidnight => self.set_hour(0),
+            Action::Screenshot => self.take_screenshot(Capture::Frame),
+            Action::ScreenshotWithOverlay => self.take_screenshot(Capture::WithOverlay),
+            // Held actions are read every frame.
+            _ => (),
+        }
+    }
+
     pub fn input(&mut self, event: &winit::event::DeviceEvent) {
         self.camera.input(event);
     }
 }
+
+fn save_screenshot(image: image::RgbaImage) -> anyhow::Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    std::fs::create_dir_all(SCREENSHOT_DIR)?;
+    let path = Path::new(SCREENSHOT_DIR).join(format!("screenshot-{}.png", timestamp));
+    image
+        .save(&path)
+        .with_context(|| format!("Couldn't write {}", path.display()))?;
+    println!("Saved screenshot to {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,362 @@
+use crate::chunk_builder::ChunkGenerator;
+use crate::coordinate::{ChunkCoord3D, Coord3DI};
+use crate::cube::{Cube, CubeType, FLUID_SOURCE_LEVEL};
+use crate::world::{World, CHUNK_I32};
+use hashbrown::{HashMap, HashSet};
+
+// Upper bound of cells updated per tick so large floods don't stall a frame.
+const MAX_UPDATES_PER_TICK: usize = 4096;
+
+const HORIZONTAL: [(i32, i32); 4] = [(-1, 0), (1, 0), (0, -1), (0, 1)];
+
+/// Level based cellular fluid simulation. Only cells next to a recent change
+/// are updated, so fluids at rest cost nothing.
+pub struct FluidSimulation {
+    active: HashSet<Coord3DI>,
+    ticks: u32,
+}
+
+impl FluidSimulation {
+    pub fn new() -> Self {
+        Self {
+            active: HashSet::new(),
+            ticks: 0,
+        }
+    }
+
+    /// Places a fluid source at a world position if its chunk is loaded.
+    pub fn place_source(
+        &mut self,
+        world: &mut World,
+        chunk_gen: &mut ChunkGenerator,
+        pos: Coord3DI,
+        cube_type: CubeType,
+    ) {
+        self.set_cube(
+            world,
+            chunk_gen,
+            pos,
+            Cube::fluid(cube_type, FLUID_SOURCE_LEVEL),
+        );
+    }
+
+    /// Replaces a cube, fluids next to it start flowing again.
+    pub fn set_cube(
+        &mut self,
+        world: &mut World,
+        chunk_gen: &mut ChunkGenerator,
+        pos: Coord3DI,
+        cube: Cube,
+    ) {
+        let mut changes = HashMap::new();
+        changes.insert(pos, cube);
+        self.apply(world, chunk_gen, changes);
+    }
+
+    pub fn update(&mut self, world: &mut World, chunk_gen: &mut ChunkGenerator) {
+        self.ticks += 1;
+        let cells = self
+            .active
+            .iter()
+            .take(MAX_UPDATES_PER_TICK)
+            .copied()
+            .collect::<Vec<_>>();
+
+        let mut changes: HashMap<Coord3DI, Cube> = HashMap::new();
+        for pos in cells {
+            self.active.remove(&pos);
+            let cube = match world.cube_at(pos) {
+                Some(c) if c.cube_type.is_fluid() => *c,
+                // Unloaded or not a fluid anymore.
+                _ => continue,
+            };
+            if self.ticks % cube.cube_type.fluid_tick_rate() != 0 {
+                self.active.insert(pos);
+                continue;
+            }
+            if !self.flow(world, pos, cube, &mut changes) {
+                // Waits for a neighbouring chunk to load.
+                self.active.insert(pos);
+            }
+        }
+        self.apply(world, chunk_gen, changes);
+    }
+
+    // Computes the changes caused by one fluid cell. Returns false if the
+    // cell borders an unloaded chunk and has to be updated again later.
+    fn flow(
+        &self,
+        world: &World,
+        pos: Coord3DI,
+        cube: Cube,
+        changes: &mut HashMap<Coord3DI, Cube>,
+    ) -> bool {
+        let fluid = cube.cube_type;
+        let at = |dx: i32, dy: i32, dz: i32| {
+            world
+                .cube_at(Coord3DI::new(pos.x + dx, pos.y + dy, pos.z + dz))
+                .copied()
+        };
+
+        // Flowing fluid is fed from above or by its highest neighbour and
+        // drains when that source is gone.
+        if cube.level < FLUID_SOURCE_LEVEL {
+            let fed = match at(0, 1, 0) {
+                Some(above) if above.cube_type == fluid => FLUID_SOURCE_LEVEL - 1,
+                _ => HORIZONTAL
+                    .iter()
+                    .filter_map(|(dx, dz)| at(*dx, 0, *dz))
+                    .filter(|n| n.cube_type == fluid)
+                    .map(|n| n.level.saturating_sub(fluid.fluid_decay()))
+                    .max()
+                    .unwrap_or(0),
+            };
+            if fed != cube.level {
+                let new = if fed == 0 {
+                    Cube::new(CubeType::AIR)
+                } else {
+                    Cube::fluid(fluid, fed)
+                };
+                push_change(changes, pos, new);
+                return true;
+            }
+        }
+
+        // Falling has priority over spreading sideways.
+        let below = match at(0, -1, 0) {
+            Some(b) => b,
+            None => return false,
+        };
+        if below.cube_type == CubeType::AIR
+            || (below.cube_type == fluid && below.level < FLUID_SOURCE_LEVEL - 1)
+        {
+            push_change(
+                changes,
+                Coord3DI::new(pos.x, pos.y - 1, pos.z),
+                Cube::fluid(fluid, FLUID_SOURCE_LEVEL - 1),
+            );
+            return true;
+        }
+        if below.cube_type == fluid {
+            return true;
+        }
+
+        let level = cube.level.saturating_sub(fluid.fluid_decay());
+        if level == 0 {
+            return true;
+        }
+        let mut loaded = true;
+        for (dx, dz) in HORIZONTAL.iter() {
+            match at(*dx, 0, *dz) {
+                Some(n)
+                    if n.cube_type == CubeType::AIR
+                        || (n.cube_type == fluid && n.level < level) =>
+                {
+                    push_change(
+                        changes,
+                        Coord3DI::new(pos.x + dx, pos.y, pos.z + dz),
+                        Cube::fluid(fluid, level),
+                    );
+                }
+                Some(_) => (),
+                None => loaded = false,
+            }
+        }
+        loaded
+    }
+
+    // Writes the changes into the world, wakes up the neighbouring cells and
+    // queues every touched chunk for a rebuild.
+    fn apply(
+        &mut self,
+        world: &mut World,
+        chunk_gen: &mut ChunkGenerator,
+        changes: HashMap<Coord3DI, Cube>,
+    ) {
+        for (pos, cube) in changes {
+            if !world.set_cube(pos, cube) {
+                continue;
+            }
+            self.active.insert(pos);
+            for (dx, dy, dz) in [
+                (-1, 0, 0),
+                (1, 0, 0),
+                (0, -1, 0),
+                (0, 1, 0),
+                (0, 0, -1),
+                (0, 0, 1),
+            ]
+            .iter()
+            {
+                self.active
+                    .insert(Coord3DI::new(pos.x + dx, pos.y + dy, pos.z + dz));
+            }
+
+            let chunk = pos.to_chunk_coord();
+            chunk_gen.enqueue_rebuild(chunk);
+            // Faces on the chunk border belong to the neighbouring mesh too.
+            let (x, z) = (pos.x.rem_euclid(CHUNK_I32), pos.z.rem_euclid(CHUNK_I32));
+            if x == 0 || x == CHUNK_I32 - 1 {
+                let dx = if x == 0 { -1 } else { 1 };
+                chunk_gen.enqueue_rebuild(ChunkCoord3D::new(chunk.x + dx, chunk.y, chunk.z));
+            }
+            if z == 0 || z == CHUNK_I32 - 1 {
+                let dz = if z == 0 { -1 } else { 1 };
+                chunk_gen.enqueue_rebuild(ChunkCoord3D::new(chunk.x, chunk.y, chunk.z + dz));
+            }
+        }
+    }
+}
+
+// Several cells can flow into the same cube in one tick, the highest
+// level wins.
+fn push_change(changes: &mut HashMap<Coord3DI, Cube>, pos: Coord3DI, cube: Cube) {
+    let entry = changes.entry(pos).or_insert(cube);
+    if cube.level > entry.level {
+        *entry = cube;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Empty chunks with a floor whose top is at y = 0.5.
+    fn world() -> World {
+        let mut world = World::with_empty_chunks();
+        for x in -16..=16 {
+            for z in -16..=16 {
+                world.set_cube(Coord3DI::new(x, 0, z), Cube::new(CubeType::DIRT));
+            }
+        }
+        world
+    }
+
+    fn run(fluids: &mut FluidSimulation, world: &mut World, ticks: u32) {
+        let mut chunk_gen = ChunkGenerator::new();
+        for _ in 0..ticks {
+            fluids.update(world, &mut chunk_gen);
+        }
+    }
+
+    fn place(fluids: &mut FluidSimulation, world: &mut World, pos: Coord3DI, fluid: CubeType) {
+        fluids.place_source(world, &mut ChunkGenerator::new(), pos, fluid);
+    }
+
+    fn cube(world: &World, x: i32, y: i32, z: i32) -> Cube {
+        *world.cube_at(Coord3DI::new(x, y, z)).unwrap()
+    }
+
+    #[test]
+    fn falls_before_spreading() {
+        let mut world = world();
+        let mut fluids = FluidSimulation::new();
+        place(
+            &mut fluids,
+            &mut world,
+            Coord3DI::new(0, 5, 0),
+            CubeType::WATER,
+        );
+        run(&mut fluids, &mut world, 50);
+        for y in 1..5 {
+            let c = cube(&world, 0, y, 0);
+            assert_eq!(c.cube_type, CubeType::WATER);
+            assert_eq!(c.level, FLUID_SOURCE_LEVEL - 1);
+        }
+        // Only spreads once it reaches the floor.
+        assert_eq!(cube(&world, 1, 5, 0).cube_type, CubeType::AIR);
+        assert_eq!(cube(&world, 1, 1, 0).level, FLUID_SOURCE_LEVEL - 2);
+    }
+
+    #[test]
+    fn loses_levels_flowing_sideways() {
+        let mut world = world();
+        let mut fluids = FluidSimulation::new();
+        place(
+            &mut fluids,
+            &mut world,
+            Coord3DI::new(0, 1, 0),
+            CubeType::WATER,
+        );
+        run(&mut fluids, &mut world, 50);
+        for x in 1..FLUID_SOURCE_LEVEL as i32 {
+            let c = cube(&world, x, 1, 0);
+            assert_eq!(c.cube_type, CubeType::WATER);
+            assert_eq!(c.level, FLUID_SOURCE_LEVEL - x as u8);
+        }
+        let end = FLUID_SOURCE_LEVEL as i32;
+        assert_eq!(cube(&world, end, 1, 0).cube_type, CubeType::AIR);
+        assert!(fluids.active.is_empty());
+    }
+
+    #[test]
+    fn drains_after_the_source_is_removed() {
+        let mut world = world();
+        let mut fluids = FluidSimulation::new();
+        place(
+            &mut fluids,
+            &mut world,
+            Coord3DI::new(0, 3, 0),
+            CubeType::WATER,
+        );
+        run(&mut fluids, &mut world, 50);
+        fluids.set_cube(
+            &mut world,
+            &mut ChunkGenerator::new(),
+            Coord3DI::new(0, 3, 0),
+            Cube::new(CubeType::AIR),
+        );
+        run(&mut fluids, &mut world, 100);
+        for x in -8..=8 {
+            for y in 1..=3 {
+                for z in -8..=8 {
+                    assert_eq!(cube(&world, x, y, z).cube_type, CubeType::AIR);
+                }
+            }
+        }
+        assert!(fluids.active.is_empty());
+    }
+
+    #[test]
+    fn lava_decays_faster() {
+        let mut world = world();
+        let mut fluids = FluidSimulation::new();
+        place(
+            &mut fluids,
+            &mut world,
+            Coord3DI::new(0, 1, 0),
+            CubeType::LAVA,
+        );
+        run(&mut fluids, &mut world, 50);
+        let decay = CubeType::LAVA.fluid_decay();
+        assert_eq!(cube(&world, 1, 1, 0).level, FLUID_SOURCE_LEVEL - decay);
+        assert_eq!(cube(&world, 2, 1, 0).level, FLUID_SOURCE_LEVEL - 2 * decay);
+        let end = (FLUID_SOURCE_LEVEL / decay) as i32;
+        assert_eq!(cube(&world, end, 1, 0).cube_type, CubeType::AIR);
+    }
+
+    #[test]
+    fn lava_flows_every_few_ticks() {
+        let mut world = world();
+        let mut fluids = FluidSimulation::new();
+        place(
+            &mut fluids,
+            &mut world,
+            Coord3DI::new(0, 1, 0),
+            CubeType::LAVA,
+        );
+        place(
+            &mut fluids,
+            &mut world,
+            Coord3DI::new(0, 1, 8),
+            CubeType::WATER,
+        );
+        run(&mut fluids, &mut world, 1);
+        assert_eq!(cube(&world, 1, 1, 8).cube_type, CubeType::WATER);
+        let rate = CubeType::LAVA.fluid_tick_rate();
+        run(&mut fluids, &mut world, rate - 2);
+        assert_eq!(cube(&world, 1, 1, 0).cube_type, CubeType::AIR);
+        run(&mut fluids, &mut world, 1);
+        assert_eq!(cube(&world, 1, 1, 0).cube_type, CubeType::LAVA);
+    }
+}
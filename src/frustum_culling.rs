@@ -1,42 +1,83 @@
 use crate::camera::Camera;
 use crate::coordinate::{ChunkCoord3D, Coord3DF};
+use crate::world::CHUNK_USIZE;
 use nalgebra::Matrix4;
 
 pub struct Frustum {
     planes: Vec<Plane>,
 }
 
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Intersection {
+    Inside,
+    Intersecting,
+    Outside,
+}
+
 impl Frustum {
     pub fn new(camera: &Camera) -> Self {
-        let mat = camera.global_matrix;
-        let planes = Frustum::matrix_to_planes(mat);
+        Frustum::from_matrix(camera.global_matrix)
+    }
+
+    pub fn from_matrix(matrix: Matrix4<f32>) -> Self {
+        let planes = Frustum::matrix_to_planes(matrix);
         Self { planes }
     }
 
+    /// Frustum of a camera at `eye` looking at `target`, for tests.
+    #[cfg(test)]
+    pub fn looking_at(eye: [f32; 3], target: [f32; 3], fov: f32, far: f32) -> Self {
+        use crate::camera::OPENGL_TO_WGPU_MATRIX;
+        use nalgebra::{Point3, Vector3};
+        let projection = Matrix4::new_perspective(1., fov.to_radians(), 0.01, far);
+        let view = Matrix4::look_at_rh(&Point3::from(eye), &Point3::from(target), &Vector3::y());
+        Frustum::from_matrix(OPENGL_TO_WGPU_MATRIX * projection * view)
+    }
+
     pub fn contains(&self, pos: &ChunkCoord3D) -> bool {
-        let p1 = pos.to_world_position_f32();
-        let mut edges = Vec::new();
-        edges.push(p1);
-        edges.push(Coord3DF::new(p1.x + 32., p1.y, p1.z));
-        edges.push(Coord3DF::new(p1.x, p1.y, p1.z + 32.));
-        edges.push(Coord3DF::new(p1.x + 32., p1.y, p1.z + 32.));
-        edges.push(Coord3DF::new(p1.x, p1.y + 32., p1.z));
-        edges.push(Coord3DF::new(p1.x + 32., p1.y + 32., p1.z));
-        edges.push(Coord3DF::new(p1.x, p1.y + 32., p1.z + 32.));
-        edges.push(Coord3DF::new(p1.x + 32., p1.y + 32., p1.z + 32.));
-        'outer: for p in 0..edges.len() {
-            for i in 0..self.planes.len() {
-                let dist = self.planes[i].a * edges[p].x as f32
-                    + self.planes[i].b * edges[p].y as f32
-                    + self.planes[i].c * edges[p].z as f32
-                    + self.planes[i].d;
-                if dist < 0. {
-                    continue 'outer;
-                }
+        self.test_chunk(pos) != Intersection::Outside
+    }
+
+    pub fn test_chunk(&self, pos: &ChunkCoord3D) -> Intersection {
+        let min = pos.to_world_position_f32();
+        let size = CHUNK_USIZE as f32;
+        let max = Coord3DF::new(min.x + size, min.y + size, min.z + size);
+        self.test_aabb(min, max)
+    }
+
+    /// Tests the box against every plane using only the corner furthest
+    /// along the plane normal (p-vertex) and the one furthest against it
+    /// (n-vertex).
+    pub fn test_aabb(&self, min: Coord3DF, max: Coord3DF) -> Intersection {
+        let mut result = Intersection::Inside;
+        for plane in self.planes.iter() {
+            let p_vertex = Coord3DF::new(
+                if plane.a >= 0. { max.x } else { min.x },
+                if plane.b >= 0. { max.y } else { min.y },
+                if plane.c >= 0. { max.z } else { min.z },
+            );
+            if plane.distance(p_vertex) < 0. {
+                return Intersection::Outside;
             }
-            return true;
+            let n_vertex = Coord3DF::new(
+                if plane.a >= 0. { min.x } else { max.x },
+                if plane.b >= 0. { min.y } else { max.y },
+                if plane.c >= 0. { min.z } else { max.z },
+            );
+            if plane.distance(n_vertex) < 0. {
+                result = Intersection::Intersecting;
+            }
+        }
+        result
+    }
+
+    /// Planes in `[a, b, c, d]` form as expected by the culling shader.
+    pub fn planes(&self) -> [[f32; 4]; 6] {
+        let mut planes = [[0.; 4]; 6];
+        for (i, p) in self.planes.iter().enumerate() {
+            planes[i] = [p.a, p.b, p.c, p.d];
         }
-        return false;
+        planes
     }
 
     pub fn update(&mut self, camera: &Camera) {
@@ -72,16 +113,21 @@ impl Frustum {
         bottom.c = data[2][3] + data[2][1];
         bottom.d = data[3][3] + data[3][1];
 
-        near.a = data[0][3] + data[0][2];
-        near.b = data[1][3] + data[1][2];
-        near.c = data[2][3] + data[2][2];
-        near.d = data[3][3] + data[3][2];
+        // wgpu clip space depth goes from 0 to 1 so the near plane is
+        // just the third row.
+        near.a = data[0][2];
+        near.b = data[1][2];
+        near.c = data[2][2];
+        near.d = data[3][2];
 
         far.a = data[0][3] - data[0][2];
         far.b = data[1][3] - data[1][2];
         far.c = data[2][3] - data[2][2];
         far.d = data[3][3] - data[3][2];
-        vec![near, far, left, right, top, bottom]
+
+        let mut planes = vec![near, far, left, right, top, bottom];
+        planes.iter_mut().for_each(Plane::normalize);
+        planes
     }
 }
 
@@ -102,4 +148,72 @@ impl Plane {
             d: 0.,
         }
     }
+
+    fn normalize(&mut self) {
+        let length = (self.a * self.a + self.b * self.b + self.c * self.c).sqrt();
+        if length > 0. {
+            self.a /= length;
+            self.b /= length;
+            self.c /= length;
+            self.d /= length;
+        }
+    }
+
+    fn distance(&self, p: Coord3DF) -> f32 {
+        self.a * p.x + self.b * p.y + self.c * p.z + self.d
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_in_front_is_inside() {
+        let f = Frustum::looking_at([16., 16., 100.], [16., 16., 0.], 60., 1000.);
+        assert_eq!(
+            f.test_chunk(&ChunkCoord3D::new(0, 0, 0)),
+            Intersection::Inside
+        );
+    }
+
+    #[test]
+    fn chunk_behind_is_outside() {
+        let f = Frustum::looking_at([16., 16., 100.], [16., 16., 0.], 60., 1000.);
+        assert_eq!(
+            f.test_chunk(&ChunkCoord3D::new(0, 0, 5)),
+            Intersection::Outside
+        );
+        assert!(!f.contains(&ChunkCoord3D::new(0, 0, 5)));
+    }
+
+    #[test]
+    fn chunk_past_far_plane_is_outside() {
+        let f = Frustum::looking_at([16., 16., 100.], [16., 16., 0.], 60., 50.);
+        assert_eq!(
+            f.test_chunk(&ChunkCoord3D::new(0, 0, -2)),
+            Intersection::Outside
+        );
+    }
+
+    #[test]
+    fn chunk_containing_camera_is_intersecting() {
+        let f = Frustum::looking_at([16., 16., 16.], [16., 16., 0.], 60., 1000.);
+        assert_eq!(
+            f.test_chunk(&ChunkCoord3D::new(0, 0, 0)),
+            Intersection::Intersecting
+        );
+    }
+
+    #[test]
+    fn straddling_chunk_with_all_corners_outside_is_visible() {
+        // The narrow frustum passes through the middle of the chunk without
+        // containing any of its corners.
+        let f = Frustum::looking_at([16., 16., 16.], [16., 16., 0.], 10., 1000.);
+        assert_eq!(
+            f.test_chunk(&ChunkCoord3D::new(0, 0, -1)),
+            Intersection::Intersecting
+        );
+        assert!(f.contains(&ChunkCoord3D::new(0, 0, -1)));
+    }
 }
@@ -0,0 +1,229 @@
+use crate::assets::Assets;
+use crate::engine::Engine;
+use crate::renderer::graphics::Graphics;
+use anyhow::{anyhow, bail, Context, Result};
+use image::RgbaImage;
+use nalgebra::Point3;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// Time the chunk generator has to stay idle before the world counts as
+// loaded, meshes of the last chunks arrive a few ticks after queuing.
+const SETTLE_TIME: Duration = Duration::from_secs(1);
+const LOAD_TIMEOUT: Duration = Duration::from_secs(120);
+// Largest mean difference per color channel, out of 255, for a frame to
+// still match its reference. Drivers don't rasterize exactly alike.
+const MAX_DIFFERENCE: f32 = 2.;
+
+/// Options of the `--screenshot <file.png>` mode, which renders a single
+/// frame without a window:
+///
+/// `--pos x,y,z` camera position, `--look yaw,pitch` in degrees,
+/// `--size WIDTHxHEIGHT` and `--hour h` for the time of day.
+/// `--compare reference.png` fails if the frame differs from the reference.
+pub struct ScreenshotOptions {
+    output: PathBuf,
+    eye: Point3<f32>,
+    yaw: f32,
+    pitch: f32,
+    size: (u32, u32),
+    hour: u32,
+    reference: Option<PathBuf>,
+}
+
+impl ScreenshotOptions {
+    /// Returns `None` if the program wasn't started in screenshot mode.
+    pub fn from_args() -> Option<Result<Self>> {
+        let args: Vec<String> = std::env::args().collect();
+        let value = |flag: &str| {
+            args.iter()
+                .position(|a| a == flag)
+                .and_then(|i| args.get(i + 1))
+                .map(String::as_str)
+        };
+        let output = value("--screenshot")?;
+        Some(Self::parse(output, value))
+    }
+
+    fn parse<'a>(output: &str, value: impl Fn(&str) -> Option<&'a str>) -> Result<Self> {
+        let eye = match value("--pos") {
+            Some(pos) => {
+                let v = parse_list(pos, ',', 3)?;
+                Point3::new(v[0], v[1], v[2])
+            }
+            None => Point3::new(0., 40., 0.),
+        };
+        let (yaw, pitch) = match value("--look") {
+            Some(look) => {
+                let v = parse_list(look, ',', 2)?;
+                (v[0], v[1])
+            }
+            None => (270., -20.),
+        };
+        let size = match value("--size") {
+            Some(size) => parse_size(size)?,
+            None => (1280, 720),
+        };
+        let hour = match value("--hour") {
+            Some(hour) => hour.parse().context("Invalid --hour")?,
+            None => 12,
+        };
+        Ok(Self {
+            output: PathBuf::from(output),
+            eye,
+            yaw,
+            pitch,
+            size,
+            hour,
+            reference: value("--compare").map(PathBuf::from),
+        })
+    }
+}
+
+fn parse_size(value: &str) -> Result<(u32, u32)> {
+    let (width, height) = value
+        .split_once('x')
+        .ok_or_else(|| anyhow!("Expected WIDTHxHEIGHT instead of '{}'", value))?;
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u32>()
+            .with_context(|| format!("Invalid size '{}'", value))
+    };
+    let size = (parse(width)?, parse(height)?);
+    if size.0 == 0 || size.1 == 0 {
+        bail!("Size '{}' has to be at least 1x1", value);
+    }
+    Ok(size)
+}
+
+fn parse_list(value: &str, separator: char, len: usize) -> Result<Vec<f32>> {
+    let values = value
+        .split(separator)
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Invalid number in '{}'", value))?;
+    if values.len() != len {
+        bail!("Expected {} values in '{}'", len, value);
+    }
+    Ok(values)
+}
+
+/// Loads the world around the camera, renders one frame and writes it to
+/// the output PNG.
+pub fn run(options: ScreenshotOptions) -> Result<()> {
+    let graphics = Graphics::new_headless(options.size.0, options.size.1)?;
+    let mut engine = Engine::new(&graphics, Assets::new());
+    let pool = uvth::ThreadPoolBuilder::new()
+        .name("Chunk Thread Pool".parse().unwrap())
+        .build();
+    // The same lighting on every run.
+    engine.set_hour(options.hour);
+    engine.set_time_frozen(true);
+    engine.place_camera(options.eye, options.yaw, options.pitch);
+
+    let start = Instant::now();
+    let mut idle_since = Instant::now();
+    while idle_since.elapsed() < SETTLE_TIME {
+        if start.elapsed() > LOAD_TIMEOUT {
+            pool.terminate();
+            return Err(anyhow!("Chunks didn't finish loading"));
+        }
+        engine.update(&graphics, &pool);
+        if !engine.chunks_loaded() {
+            idle_since = Instant::now();
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+
+    let image = engine.screenshot(&graphics);
+    pool.terminate();
+    let image = image?;
+    image
+        .save(&options.output)
+        .with_context(|| format!("Couldn't write {}", options.output.display()))?;
+    println!("Saved screenshot to {}", options.output.display());
+
+    if let Some(path) = &options.reference {
+        let reference = image::open(path)
+            .with_context(|| format!("Couldn't read {}", path.display()))?
+            .to_rgba8();
+        let difference = mean_difference(&image, &reference)?;
+        if difference > MAX_DIFFERENCE {
+            bail!(
+                "Differs from {} by {:.2} per channel",
+                path.display(),
+                difference
+            );
+        }
+        println!("Matches {} ({:.2} per channel)", path.display(), difference);
+    }
+    Ok(())
+}
+
+// Mean absolute difference of the color channels, alpha is ignored.
+fn mean_difference(image: &RgbaImage, reference: &RgbaImage) -> Result<f32> {
+    if image.dimensions() != reference.dimensions() {
+        bail!(
+            "Size {:?} doesn't match the reference size {:?}",
+            image.dimensions(),
+            reference.dimensions()
+        );
+    }
+    let total: u64 = image
+        .pixels()
+        .zip(reference.pixels())
+        .map(|(a, b)| {
+            (0..3)
+                .map(|c| (a.0[c] as i64 - b.0[c] as i64).abs() as u64)
+                .sum::<u64>()
+        })
+        .sum();
+    Ok(total as f32 / (image.width() * image.height() * 3) as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn parse_args(args: &[(&'static str, &'static str)]) -> Result<ScreenshotOptions> {
+        ScreenshotOptions::parse("out.png", |flag| {
+            args.iter().find(|(f, _)| *f == flag).map(|(_, v)| *v)
+        })
+    }
+
+    #[test]
+    fn parses_sizes() {
+        let options = parse_args(&[("--size", "640x360")]).unwrap();
+        assert_eq!(options.size, (640, 360));
+        assert_eq!(parse_args(&[]).unwrap().size, (1280, 720));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        for size in ["0x0", "640x0", "-5x10", "12.7x9", "640", "640x360x2", ""] {
+            assert!(parse_args(&[("--size", size)]).is_err(), "{}", size);
+        }
+    }
+
+    #[test]
+    fn identical_images_have_no_difference() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255]));
+        assert_eq!(mean_difference(&image, &image.clone()).unwrap(), 0.);
+    }
+
+    #[test]
+    fn difference_is_averaged_over_color_channels() {
+        let image = RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 255]));
+        let mut reference = image.clone();
+        // Alpha doesn't count.
+        reference.put_pixel(0, 0, Rgba([12, 0, 0, 0]));
+        assert_eq!(mean_difference(&image, &reference).unwrap(), 1.);
+    }
+
+    #[test]
+    fn images_of_different_sizes_differ() {
+        let image = RgbaImage::new(2, 2);
+        assert!(mean_difference(&image, &RgbaImage::new(2, 3)).is_err());
+    }
+}
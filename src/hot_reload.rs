@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+// How often the watched directories are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+pub enum Change {
+    /// A shader source changed, with its file name and new contents.
+    Shader(String, String),
+    /// Any of the block textures changed.
+    Textures,
+    /// The key bindings file changed.
+    Bindings,
+}
+
+/// Polls the shader sources, the texture directory and the key bindings
+/// for modified files.
+pub struct FileWatcher {
+    shader_dir: PathBuf,
+    texture_dir: PathBuf,
+    bindings: PathBuf,
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: Instant,
+}
+
+impl FileWatcher {
+    pub fn new(shader_dir: PathBuf, texture_dir: PathBuf, bindings: PathBuf) -> Self {
+        let mut watcher = Self {
+            shader_dir,
+            texture_dir,
+            bindings,
+            modified: HashMap::new(),
+            last_poll: Instant::now(),
+        };
+        // Only changes after startup are reported.
+        watcher.changed_files(&watcher.shader_dir.clone());
+        watcher.changed_files(&watcher.texture_dir.clone());
+        watcher.file_changed(&watcher.bindings.clone());
+        watcher
+    }
+
+    /// Shaders are embedded at build time, so by default changes are picked
+    /// up from the sources they were built from. Outside of the source tree
+    /// `--shaders <dir>` points to a copy of `src/shaders` to edit instead.
+    pub fn shader_dir_from_args() -> PathBuf {
+        let mut args = std::env::args().skip_while(|a| a != "--shaders").skip(1);
+        args.next()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("src/shaders"))
+    }
+
+    pub fn poll(&mut self) -> Vec<Change> {
+        let mut changes = Vec::new();
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return changes;
+        }
+        self.last_poll = Instant::now();
+
+        for path in self.changed_files(&self.shader_dir.clone()) {
+            let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+            // Editors may still be writing the file, it is read again on the
+            // next change.
+            if let Ok(source) = std::fs::read_to_string(&path) {
+                changes.push(Change::Shader(file_name, source));
+            }
+        }
+        if !self.changed_files(&self.texture_dir.clone()).is_empty() {
+            changes.push(Change::Textures);
+        }
+        if self.file_changed(&self.bindings.clone()) {
+            changes.push(Change::Bindings);
+        }
+        changes
+    }
+
+    // Files in `dir` whose modification time differs from the last poll.
+    fn changed_files(&mut self, dir: &Path) -> Vec<PathBuf> {
+        let mut changed = Vec::new();
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return changed,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if self.file_changed(&path) {
+                changed.push(path);
+            }
+        }
+        changed
+    }
+
+    // Whether the modification time of a file differs from the last poll.
+    // Files that don't exist or can't be read count as unchanged.
+    fn file_changed(&mut self, path: &Path) -> bool {
+        let modified = match std::fs::metadata(path) {
+            Ok(metadata) if metadata.is_file() => match metadata.modified() {
+                Ok(modified) => modified,
+                Err(_) => return false,
+            },
+            _ => return false,
+        };
+        self.modified.insert(path.to_path_buf(), modified) != Some(modified)
+    }
+}
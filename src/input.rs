@@ -0,0 +1,513 @@
+use anyhow::{anyhow, bail, Context, Result};
+use gilrs::{Axis, Button, EventType, Gilrs};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use winit::event::{MouseButton, VirtualKeyCode};
+
+const BINDINGS_FILE: &str = "bindings.cfg";
+// Stick values below this are treated as centered.
+const DEAD_ZONE: f32 = 0.2;
+// Inputs count as pressed above this value.
+const PRESS_THRESHOLD: f32 = 0.5;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    MoveLeft,
+    MoveRight,
+    /// Jumps while walking and moves up while flying.
+    Jump,
+    Descend,
+    Sprint,
+    Crouch,
+    LookUp,
+    LookDown,
+    LookLeft,
+    LookRight,
+    Break,
+    Place,
+    /// Switches to the next cube `Place` puts down.
+    CycleCube,
+    PlaceWater,
+    PlaceLava,
+    ToggleDebug,
+    ToggleWalking,
+    ToggleViewBobbing,
+    CycleCameraMode,
+    ToggleOcclusionCulling,
+    CycleDebugView,
+    CycleMsaa,
+    RenderDistanceUp,
+    RenderDistanceDown,
+    ToggleTime,
+    HourBack,
+    HourForward,
+    SetNoon,
+    SetMidnight,
+    Screenshot,
+    ScreenshotWithOverlay,
+    ReleaseCursor,
+    Quit,
+}
+
+// Names of the actions in the bindings file.
+const ACTIONS: [(Action, &str); 35] = [
+    (Action::MoveForward, "move_forward"),
+    (Action::MoveBackward, "move_backward"),
+    (Action::MoveLeft, "move_left"),
+    (Action::MoveRight, "move_right"),
+    (Action::Jump, "jump"),
+    (Action::Descend, "descend"),
+    (Action::Sprint, "sprint"),
+    (Action::Crouch, "crouch"),
+    (Action::LookUp, "look_up"),
+    (Action::LookDown, "look_down"),
+    (Action::LookLeft, "look_left"),
+    (Action::LookRight, "look_right"),
+    (Action::Break, "break"),
+    (Action::Place, "place"),
+    (Action::CycleCube, "cycle_cube"),
+    (Action::PlaceWater, "place_water"),
+    (Action::PlaceLava, "place_lava"),
+    (Action::ToggleDebug, "toggle_debug"),
+    (Action::ToggleWalking, "toggle_walking"),
+    (Action::ToggleViewBobbing, "toggle_view_bobbing"),
+    (Action::CycleCameraMode, "cycle_camera_mode"),
+    (Action::ToggleOcclusionCulling, "toggle_occlusion_culling"),
+    (Action::CycleDebugView, "cycle_debug_view"),
+    (Action::CycleMsaa, "cycle_msaa"),
+    (Action::RenderDistanceUp, "render_distance_up"),
+    (Action::RenderDistanceDown, "render_distance_down"),
+    (Action::ToggleTime, "toggle_time"),
+    (Action::HourBack, "hour_back"),
+    (Action::HourForward, "hour_forward"),
+    (Action::SetNoon, "set_noon"),
+    (Action::SetMidnight, "set_midnight"),
+    (Action::Screenshot, "screenshot"),
+    (Action::ScreenshotWithOverlay, "screenshot_with_overlay"),
+    (Action::ReleaseCursor, "release_cursor"),
+    (Action::Quit, "quit"),
+];
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum Input {
+    Key(VirtualKeyCode),
+    Mouse(MouseButton),
+    GamepadButton(Button),
+    /// One direction of a gamepad axis, `true` for the positive one.
+    GamepadAxis(Axis, bool),
+}
+
+macro_rules! names {
+    ($prefix:literal, $type:ident: $($name:ident),* $(,)?) => {
+        &[$((concat!($prefix, stringify!($name)), $type::$name)),*]
+    };
+}
+
+// Keys that can be bound, named like their `VirtualKeyCode`.
+const KEYS: &[(&str, VirtualKeyCode)] = names!("", VirtualKeyCode:
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Key0, Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Escape, Tab, Space, Return, Back, Delete, Insert, Home, End, PageUp, PageDown,
+    Up, Down, Left, Right, LShift, RShift, LControl, RControl, LAlt, RAlt,
+    Minus, Equals, LBracket, RBracket, Semicolon, Apostrophe, Comma, Period, Slash,
+    Backslash, Grave, NumpadAdd, NumpadSubtract,
+);
+
+const BUTTONS: &[(&str, Button)] = names!("Pad", Button:
+    South, East, North, West, LeftTrigger, LeftTrigger2, RightTrigger, RightTrigger2,
+    Select, Start, Mode, LeftThumb, RightThumb, DPadUp, DPadDown, DPadLeft, DPadRight,
+);
+
+const AXES: &[(&str, Axis)] = names!("Pad", Axis:
+    LeftStickX, LeftStickY, RightStickX, RightStickY,
+);
+
+fn find<T: Copy>(table: &[(&str, T)], name: &str) -> Option<T> {
+    table.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+}
+
+impl Input {
+    fn parse(name: &str) -> Result<Self> {
+        let input = match name {
+            "MouseLeft" => Some(Input::Mouse(MouseButton::Left)),
+            "MouseRight" => Some(Input::Mouse(MouseButton::Right)),
+            "MouseMiddle" => Some(Input::Mouse(MouseButton::Middle)),
+            _ => find(KEYS, name)
+                .map(Input::Key)
+                .or_else(|| find(BUTTONS, name).map(Input::GamepadButton))
+                .or_else(|| {
+                    let (axis, positive) = match name.strip_suffix('+') {
+                        Some(axis) => (axis, true),
+                        None => (name.strip_suffix('-')?, false),
+                    };
+                    find(AXES, axis).map(|axis| Input::GamepadAxis(axis, positive))
+                }),
+        };
+        input.ok_or_else(|| anyhow!("Unknown input '{}'", name))
+    }
+}
+
+/// Inputs each action is bound to.
+pub struct Bindings {
+    inputs: HashMap<Action, Vec<Input>>,
+}
+
+impl Bindings {
+    /// Path of the bindings file, `--bindings <file>` or `bindings.cfg`
+    /// next to the executable, and the one in the source tree otherwise.
+    pub fn path_from_args() -> PathBuf {
+        let mut args = std::env::args().skip_while(|a| a != "--bindings").skip(1);
+        args.next()
+            .map(PathBuf::from)
+            .or_else(|| {
+                let exe = std::env::current_exe().ok()?;
+                Some(exe.parent()?.join(BINDINGS_FILE)).filter(|p| p.is_file())
+            })
+            .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join(BINDINGS_FILE))
+    }
+
+    /// Reads the bindings file, made of lines like `jump = Space, PadSouth`.
+    /// Actions missing from the file keep their default inputs and a file
+    /// that doesn't exist leaves all of them.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut bindings = Bindings::default();
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(bindings),
+            Err(e) => return Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+        };
+        for (number, line) in text.lines().enumerate() {
+            bindings
+                .parse_line(line)
+                .with_context(|| format!("{}:{}", path.display(), number + 1))?;
+        }
+        Ok(bindings)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<()> {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            return Ok(());
+        }
+        let (name, inputs) = match line.split_once('=') {
+            Some(binding) => binding,
+            None => bail!("Expected 'action = inputs'"),
+        };
+        let name = name.trim();
+        let action = ACTIONS
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(action, _)| *action)
+            .ok_or_else(|| anyhow!("Unknown action '{}'", name))?;
+        let inputs = inputs
+            .split(',')
+            .map(str::trim)
+            .filter(|i| !i.is_empty())
+            .map(Input::parse)
+            .collect::<Result<Vec<_>>>()?;
+        self.bind(action, inputs);
+        Ok(())
+    }
+
+    /// Replaces the inputs of an action, no inputs unbind it.
+    pub fn bind(&mut self, action: Action, inputs: Vec<Input>) {
+        self.inputs.insert(action, inputs);
+    }
+
+    fn actions(&self, input: Input) -> impl Iterator<Item = Action> + '_ {
+        self.inputs
+            .iter()
+            .filter(move |(_, inputs)| inputs.contains(&input))
+            .map(|(action, _)| *action)
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Action::*;
+        use VirtualKeyCode as Key;
+        let (key, mouse, pad, axis) = (
+            Input::Key,
+            Input::Mouse,
+            Input::GamepadButton,
+            Input::GamepadAxis,
+        );
+        let defaults = vec![
+            (MoveForward, vec![key(Key::W), axis(Axis::LeftStickY, true)]),
+            (
+                MoveBackward,
+                vec![key(Key::S), axis(Axis::LeftStickY, false)],
+            ),
+            (MoveLeft, vec![key(Key::A), axis(Axis::LeftStickX, false)]),
+            (MoveRight, vec![key(Key::D), axis(Axis::LeftStickX, true)]),
+            (Jump, vec![key(Key::Space), pad(Button::South)]),
+            (Descend, vec![key(Key::LShift), pad(Button::East)]),
+            (Sprint, vec![key(Key::LControl), pad(Button::LeftThumb)]),
+            (Crouch, vec![key(Key::C), pad(Button::RightThumb)]),
+            (LookUp, vec![axis(Axis::RightStickY, true)]),
+            (LookDown, vec![axis(Axis::RightStickY, false)]),
+            (LookLeft, vec![axis(Axis::RightStickX, false)]),
+            (LookRight, vec![axis(Axis::RightStickX, true)]),
+            (
+                Break,
+                vec![mouse(MouseButton::Left), pad(Button::RightTrigger2)],
+            ),
+            (
+                Place,
+                vec![mouse(MouseButton::Right), pad(Button::LeftTrigger2)],
+            ),
+            (CycleCube, vec![key(Key::Q), pad(Button::DPadRight)]),
+            (PlaceWater, vec![key(Key::F)]),
+            (PlaceLava, vec![key(Key::L)]),
+            (ToggleDebug, vec![key(Key::F1), pad(Button::Select)]),
+            (ToggleWalking, vec![key(Key::G), pad(Button::North)]),
+            (ToggleViewBobbing, vec![key(Key::B)]),
+            (CycleCameraMode, vec![key(Key::F5), pad(Button::DPadUp)]),
+            (ToggleOcclusionCulling, vec![key(Key::O)]),
+            (CycleDebugView, vec![key(Key::V)]),
+            (CycleMsaa, vec![key(Key::K)]),
+            (
+                RenderDistanceUp,
+                vec![key(Key::Equals), key(Key::NumpadAdd)],
+            ),
+            (
+                RenderDistanceDown,
+                vec![key(Key::Minus), key(Key::NumpadSubtract)],
+            ),
+            (ToggleTime, vec![key(Key::T)]),
+            (HourBack, vec![key(Key::LBracket)]),
+            (HourForward, vec![key(Key::RBracket)]),
+            (SetNoon, vec![key(Key::N)]),
+            (SetMidnight, vec![key(Key::M)]),
+            (Screenshot, vec![key(Key::F2)]),
+            (ScreenshotWithOverlay, vec![key(Key::F3)]),
+            (ReleaseCursor, vec![key(Key::Tab), pad(Button::Start)]),
+            (Quit, vec![key(Key::Escape)]),
+        ];
+        Self {
+            inputs: defaults.into_iter().collect(),
+        }
+    }
+}
+
+/// Turns keyboard, mouse and gamepad input into actions.
+pub struct InputState {
+    bindings: Bindings,
+    bindings_path: PathBuf,
+    // Current value of every input that isn't released, 1 for pressed keys.
+    values: HashMap<Input, f32>,
+    // Actions whose inputs were pressed since the last `take_triggered`.
+    triggered: Vec<Action>,
+    // `None` if gamepads aren't supported on this system.
+    gilrs: Option<Gilrs>,
+}
+
+impl InputState {
+    /// Loads the bindings from `Bindings::path_from_args`, errors are
+    /// reported and the default bindings are used instead.
+    pub fn new() -> Self {
+        let bindings_path = Bindings::path_from_args();
+        let bindings = Bindings::load(&bindings_path).unwrap_or_else(|e| {
+            eprintln!("{:#}", e);
+            Bindings::default()
+        });
+        let gilrs = Gilrs::new()
+            .map_err(|e| eprintln!("Gamepads are not available: {}", e))
+            .ok();
+        Self {
+            bindings,
+            bindings_path,
+            values: HashMap::new(),
+            triggered: Vec::new(),
+            gilrs,
+        }
+    }
+
+    pub fn bindings_path(&self) -> &Path {
+        &self.bindings_path
+    }
+
+    /// Loads the bindings file again, keeps the current bindings if that
+    /// fails.
+    pub fn reload_bindings(&mut self) -> Result<(), String> {
+        self.bindings = Bindings::load(&self.bindings_path).map_err(|e| format!("{:#}", e))?;
+        Ok(())
+    }
+
+    pub fn key(&mut self, key: VirtualKeyCode, pressed: bool) {
+        self.set(Input::Key(key), if pressed { 1. } else { 0. });
+    }
+
+    pub fn mouse(&mut self, button: MouseButton, pressed: bool) {
+        self.set(Input::Mouse(button), if pressed { 1. } else { 0. });
+    }
+
+    /// Releases all inputs, for example when the window loses focus and
+    /// releases wouldn't arrive.
+    pub fn release_all(&mut self) {
+        self.values.clear();
+    }
+
+    /// Reads the pending gamepad events.
+    pub fn poll_gamepads(&mut self) {
+        let mut changes = Vec::new();
+        if let Some(gilrs) = &mut self.gilrs {
+            while let Some(event) = gilrs.next_event() {
+                match event.event {
+                    EventType::ButtonPressed(button, _) => {
+                        changes.push((Input::GamepadButton(button), 1.))
+                    }
+                    EventType::ButtonReleased(button, _) => {
+                        changes.push((Input::GamepadButton(button), 0.))
+                    }
+                    EventType::AxisChanged(axis, value, _) => {
+                        let value = if value.abs() < DEAD_ZONE { 0. } else { value };
+                        changes.push((Input::GamepadAxis(axis, true), value.max(0.)));
+                        changes.push((Input::GamepadAxis(axis, false), (-value).max(0.)));
+                    }
+                    EventType::Disconnected => self.values.retain(|input, _| {
+                        !matches!(input, Input::GamepadButton(_) | Input::GamepadAxis(..))
+                    }),
+                    _ => (),
+                }
+            }
+        }
+        for (input, value) in changes {
+            self.set(input, value);
+        }
+    }
+
+    /// How far an action is held, between 0 and 1. Analog sticks give
+    /// values in between.
+    pub fn value(&self, action: Action) -> f32 {
+        self.bindings
+            .inputs
+            .get(&action)
+            .into_iter()
+            .flatten()
+            .filter_map(|input| self.values.get(input))
+            .fold(0., |max, &value| f32::max(max, value))
+    }
+
+    /// Actions triggered since the last call, in order.
+    pub fn take_triggered(&mut self) -> Vec<Action> {
+        std::mem::take(&mut self.triggered)
+    }
+
+    fn set(&mut self, input: Input, value: f32) {
+        let previous = self.values.get(&input).copied().unwrap_or(0.);
+        if previous <= PRESS_THRESHOLD && value > PRESS_THRESHOLD {
+            self.triggered.extend(self.bindings.actions(input));
+        }
+        if value > 0. {
+            self.values.insert(input, value);
+        } else {
+            self.values.remove(&input);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty() -> Bindings {
+        Bindings {
+            inputs: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn parses_keys_buttons_and_axes() {
+        assert_eq!(
+            Input::parse("Space").unwrap(),
+            Input::Key(VirtualKeyCode::Space)
+        );
+        assert_eq!(
+            Input::parse("MouseRight").unwrap(),
+            Input::Mouse(MouseButton::Right)
+        );
+        assert_eq!(
+            Input::parse("PadSouth").unwrap(),
+            Input::GamepadButton(Button::South)
+        );
+        assert_eq!(
+            Input::parse("PadLeftStickY+").unwrap(),
+            Input::GamepadAxis(Axis::LeftStickY, true)
+        );
+        assert_eq!(
+            Input::parse("PadRightStickX-").unwrap(),
+            Input::GamepadAxis(Axis::RightStickX, false)
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_inputs() {
+        assert!(Input::parse("Spacebar").is_err());
+        assert!(Input::parse("South").is_err());
+        // Axes need a direction.
+        assert!(Input::parse("PadLeftStickY").is_err());
+        assert!(Input::parse("").is_err());
+    }
+
+    #[test]
+    fn line_binds_all_inputs() {
+        let mut bindings = empty();
+        bindings.parse_line(" jump =Space,  PadSouth ").unwrap();
+        assert_eq!(
+            bindings.inputs[&Action::Jump],
+            vec![
+                Input::Key(VirtualKeyCode::Space),
+                Input::GamepadButton(Button::South)
+            ]
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_ignored() {
+        let mut bindings = empty();
+        bindings.parse_line("").unwrap();
+        bindings.parse_line("   # jump = Space").unwrap();
+        assert!(bindings.inputs.is_empty());
+        bindings
+            .parse_line("quit = Escape # and nothing else")
+            .unwrap();
+        assert_eq!(
+            bindings.inputs[&Action::Quit],
+            vec![Input::Key(VirtualKeyCode::Escape)]
+        );
+    }
+
+    #[test]
+    fn empty_inputs_unbind_the_action() {
+        let mut bindings = Bindings::default();
+        bindings.parse_line("quit =").unwrap();
+        assert!(bindings.inputs[&Action::Quit].is_empty());
+    }
+
+    #[test]
+    fn invalid_lines_are_rejected() {
+        let mut bindings = Bindings::default();
+        assert!(bindings.parse_line("jump Space").is_err());
+        assert!(bindings.parse_line("fly = Space").is_err());
+        assert!(bindings.parse_line("jump = Space, Spacebar").is_err());
+        // A line with an error changes nothing.
+        assert_eq!(
+            bindings.inputs[&Action::Jump],
+            Bindings::default().inputs[&Action::Jump]
+        );
+    }
+
+    #[test]
+    fn bindings_file_lists_the_defaults() {
+        let mut bindings = empty();
+        for line in include_str!("../bindings.cfg").lines() {
+            bindings.parse_line(line).unwrap();
+        }
+        assert_eq!(bindings.inputs.len(), ACTIONS.len());
+        assert_eq!(bindings.inputs, Bindings::default().inputs);
+    }
+}
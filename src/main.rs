@@ -1,25 +1,37 @@
-use winit::event::{ElementState, Event, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent};
+use winit::event::{ElementState, Event, KeyboardInput, MouseButton, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::WindowBuilder;
 
+mod assets;
 mod camera;
 mod chunk;
 mod chunk_builder;
+mod chunk_cache;
+mod clock;
 mod coordinate;
 mod cube;
 mod debug_info;
 mod engine;
+mod fluid;
 mod frustum_culling;
+mod headless;
+mod hot_reload;
+mod input;
 mod perlin_noise;
+mod physics;
 mod player;
 mod quad;
 mod renderer;
 mod texture;
 mod uniform;
+mod visibility;
 mod world;
 
 use crate::renderer::graphics::Graphics;
+use assets::Assets;
 use engine::Engine;
+use headless::ScreenshotOptions;
+use input::Action;
 
 struct Client {
     graphics: Graphics,
@@ -30,7 +42,7 @@ struct Client {
 impl Client {
     fn new(window: &winit::window::Window) -> Self {
         let graphics = Graphics::new(&window);
-        let engine = Engine::new(&graphics);
+        let engine = Engine::new(&graphics, Assets::new());
         let pool = uvth::ThreadPoolBuilder::new()
             .name("Chunk Thread Pool".parse().unwrap())
             .build();
@@ -57,6 +69,13 @@ impl Client {
 
 fn main() {
     wgpu_subscriber::initialize_default_subscriber(None);
+    if let Some(options) = ScreenshotOptions::from_args() {
+        if let Err(e) = options.and_then(headless::run) {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
     let event_loop = EventLoop::new();
     let window = WindowBuilder::new().build(&event_loop).unwrap();
 
@@ -73,32 +92,23 @@ fn main() {
                 WindowEvent::KeyboardInput {
                     input:
                         KeyboardInput {
-                            state: ElementState::Pressed,
-                            virtual_keycode,
+                            state,
+                            virtual_keycode: Some(key),
                             ..
                         },
                     ..
-                } => match virtual_keycode.unwrap() {
-                    VirtualKeyCode::Escape => quit(&mut client, control_flow),
-                    _ => (),
-                },
+                } => client.engine.input.key(key, state == ElementState::Pressed),
+                // The first click only grabs the cursor.
                 WindowEvent::MouseInput {
                     state: ElementState::Pressed,
-                    button,
+                    button: MouseButton::Left,
                     ..
-                } => match button {
-                    MouseButton::Left => {
-                        focus = true;
-                        window.set_cursor_visible(false);
-                        window.set_cursor_grab(true).unwrap();
-                    }
-                    MouseButton::Right => {
-                        focus = false;
-                        window.set_cursor_visible(true);
-                        window.set_cursor_grab(false).unwrap();
-                    }
-                    _ => (),
-                },
+                } if !focus => set_focus(&window, &mut focus, true),
+                WindowEvent::MouseInput { state, button, .. } => client
+                    .engine
+                    .input
+                    .mouse(button, state == ElementState::Pressed),
+                WindowEvent::Focused(false) => client.engine.input.release_all(),
                 WindowEvent::Resized(new_size) => client.resize(new_size),
                 WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
                     client.resize(*new_inner_size)
@@ -116,7 +126,17 @@ fn main() {
                     ))
                     .unwrap();
             }
-            Event::MainEventsCleared => window.request_redraw(),
+            Event::MainEventsCleared => {
+                client.engine.input.poll_gamepads();
+                for action in client.engine.input.take_triggered() {
+                    match action {
+                        Action::Quit => quit(&mut client, control_flow),
+                        Action::ReleaseCursor => set_focus(&window, &mut focus, false),
+                        action => client.engine.perform(action, &client.graphics),
+                    }
+                }
+                window.request_redraw()
+            }
 
             Event::RedrawRequested(_) => {
                 match client.render() {
@@ -134,6 +154,13 @@ fn main() {
     })
 }
 
+// Focused windows hide and capture the cursor to turn the camera.
+fn set_focus(window: &winit::window::Window, focus: &mut bool, focused: bool) {
+    *focus = focused;
+    window.set_cursor_visible(!focused);
+    window.set_cursor_grab(focused).unwrap();
+}
+
 fn quit(client: &mut Client, control_flow: &mut ControlFlow) {
     client.pool.terminate();
     *control_flow = ControlFlow::Exit;
@@ -0,0 +1,392 @@
+use crate::coordinate::Coord3DI;
+use crate::world::World;
+use nalgebra::{Point3, Vector3};
+
+// Cubes per second squared.
+const GRAVITY: f32 = 32.;
+const TERMINAL_VELOCITY: f32 = 60.;
+// Reaches a height of about 1.25 cubes.
+const JUMP_VELOCITY: f32 = 9.;
+// Cubes are a full cube tall, so anything lower than one cube never helps.
+const STEP_HEIGHT: f32 = 1.;
+// Gap kept between a body and the cubes it touches, so rounding errors
+// don't leave it inside of them.
+const SKIN: f32 = 0.001;
+// Boxes closer than this on an axis don't count as overlapping.
+const EPSILON: f32 = 0.0001;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn new(min: Point3<f32>, max: Point3<f32>) -> Self {
+        Self { min, max }
+    }
+
+    /// Space taken up by the cube at `pos`, cubes are centred on their
+    /// coordinates.
+    pub fn cube(pos: Coord3DI) -> Self {
+        let center = Point3::new(pos.x as f32, pos.y as f32, pos.z as f32);
+        Self::new(center - Vector3::repeat(0.5), center + Vector3::repeat(0.5))
+    }
+
+    pub fn translated(&self, offset: Vector3<f32>) -> Self {
+        Self::new(self.min + offset, self.max + offset)
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|a| self.min[a] < other.max[a] - EPSILON && self.max[a] > other.min[a] + EPSILON)
+    }
+
+    // Whether both boxes overlap on the two axes other than `axis`.
+    fn overlaps_across(&self, other: &Aabb, axis: usize) -> bool {
+        (0..3)
+            .filter(|&a| a != axis)
+            .all(|a| self.min[a] < other.max[a] - EPSILON && self.max[a] > other.min[a] + EPSILON)
+    }
+
+    // Positions of all cubes the box touches.
+    fn cubes(&self) -> impl Iterator<Item = Coord3DI> {
+        let min = self.min.map(|v| (v + 0.5).floor() as i32);
+        let max = self.max.map(|v| (v + 0.5).floor() as i32);
+        (min.x..=max.x).flat_map(move |x| {
+            (min.y..=max.y).flat_map(move |y| (min.z..=max.z).map(move |z| Coord3DI::new(x, y, z)))
+        })
+    }
+}
+
+/// Box that falls and collides with the solid cubes of the world.
+pub struct Body {
+    /// Center of the bottom face.
+    pub position: Point3<f32>,
+    pub velocity: Vector3<f32>,
+    pub on_ground: bool,
+    half_width: f32,
+    height: f32,
+}
+
+impl Body {
+    pub fn new(position: Point3<f32>, width: f32, height: f32) -> Self {
+        Self {
+            position,
+            velocity: Vector3::zeros(),
+            on_ground: false,
+            half_width: width / 2.,
+            height,
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        Aabb::new(
+            self.position - Vector3::new(self.half_width, 0., self.half_width),
+            self.position + Vector3::new(self.half_width, self.height, self.half_width),
+        )
+    }
+
+    pub fn jump(&mut self) {
+        if self.on_ground {
+            self.velocity.y = JUMP_VELOCITY;
+        }
+    }
+
+    /// Applies gravity and moves the body along its velocity for `dt`
+    /// seconds. A body on the ground walking into a cube steps onto it if
+    /// there is room above.
+    pub fn step(&mut self, world: &World, dt: f32) {
+        self.velocity.y = (self.velocity.y - GRAVITY * dt).max(-TERMINAL_VELOCITY);
+        let delta = self.velocity * dt;
+        let start = self.aabb();
+        let mut moved = sweep(world, &start, delta);
+
+        // Moves that aren't clipped keep their exact length.
+        let blocked = moved.x != delta.x || moved.z != delta.z;
+        if self.on_ground && blocked {
+            // Tries the horizontal move again from higher up and settles
+            // back down afterwards.
+            let up = sweep(world, &start, Vector3::new(0., STEP_HEIGHT, 0.));
+            let lifted = start.translated(up);
+            let across = sweep(world, &lifted, Vector3::new(delta.x, 0., delta.z));
+            let down = sweep(
+                world,
+                &lifted.translated(across),
+                Vector3::new(0., -up.y, 0.),
+            );
+            let stepped = up + across + down;
+            if stepped.xz().norm() > moved.xz().norm() + EPSILON {
+                moved = stepped;
+            }
+        }
+
+        self.on_ground = delta.y < 0. && moved.y > delta.y;
+        for axis in 0..3 {
+            if moved[axis] != delta[axis] {
+                self.velocity[axis] = 0.;
+            }
+        }
+        self.position += moved;
+    }
+}
+
+/// Whether bodies collide with the cube at `pos`. Cubes of chunks that
+/// aren't loaded yet are solid, so nothing falls out of the world while
+/// it loads.
+pub fn is_solid(world: &World, pos: Coord3DI) -> bool {
+    world
+        .cube_at(pos)
+        .map_or(true, |cube| cube.cube_type.is_solid())
+}
+
+/// First solid cube along a ray.
+#[derive(Copy, Clone, Debug)]
+pub struct RayHit {
+    pub cube: Coord3DI,
+    /// Cube the ray went through right before, in front of the face it hit.
+    pub previous: Coord3DI,
+    /// Distance from the start of the ray to the face it hit.
+    pub distance: f32,
+}
+
+/// Visits the cubes along a ray one after another and returns the first
+/// solid one closer than `max`. `direction` has to be normalized.
+pub fn raycast(
+    world: &World,
+    from: Point3<f32>,
+    direction: Vector3<f32>,
+    max: f32,
+) -> Option<RayHit> {
+    // Shifted so cube borders are at integer coordinates.
+    let origin = from + Vector3::repeat(0.5);
+    let mut cube = origin.map(|v| v.floor() as i32);
+    let mut step = [0; 3];
+    // Distance along the ray to the next border on each axis and between
+    // two borders on that axis.
+    let mut next = Vector3::repeat(f32::INFINITY);
+    let mut delta = Vector3::repeat(f32::INFINITY);
+    for axis in 0..3 {
+        if direction[axis] > 0. {
+            step[axis] = 1;
+            delta[axis] = 1. / direction[axis];
+            next[axis] = (cube[axis] as f32 + 1. - origin[axis]) * delta[axis];
+        } else if direction[axis] < 0. {
+            step[axis] = -1;
+            delta[axis] = -1. / direction[axis];
+            next[axis] = (origin[axis] - cube[axis] as f32) * delta[axis];
+        }
+    }
+
+    let mut previous = Coord3DI::new(cube.x, cube.y, cube.z);
+    let mut distance = 0.;
+    loop {
+        let pos = Coord3DI::new(cube.x, cube.y, cube.z);
+        if is_solid(world, pos) {
+            return Some(RayHit {
+                cube: pos,
+                previous,
+                distance,
+            });
+        }
+        let axis = next.imin();
+        distance = next[axis];
+        if distance > max {
+            return None;
+        }
+        previous = pos;
+        cube[axis] += step[axis];
+        next[axis] += delta[axis];
+    }
+}
+
+// Moves the box one axis at a time, vertical first, and returns how far it
+// got before hitting solid cubes.
+fn sweep(world: &World, aabb: &Aabb, delta: Vector3<f32>) -> Vector3<f32> {
+    let mut aabb = *aabb;
+    let mut moved = Vector3::zeros();
+    for &axis in &[1, 0, 2] {
+        moved[axis] = clip_axis(world, &aabb, axis, delta[axis]);
+        let mut offset = Vector3::zeros();
+        offset[axis] = moved[axis];
+        aabb = aabb.translated(offset);
+    }
+    moved
+}
+
+// Shortens a move along `axis` so the box stops in front of the first solid
+// cube in its way. Cubes the box already overlaps are ignored, so it can
+// move out of them.
+fn clip_axis(world: &World, aabb: &Aabb, axis: usize, delta: f32) -> f32 {
+    if delta == 0. {
+        return 0.;
+    }
+    let mut swept = *aabb;
+    if delta > 0. {
+        swept.max[axis] += delta;
+    } else {
+        swept.min[axis] += delta;
+    }
+    let mut delta = delta;
+    for pos in swept.cubes() {
+        let cube = Aabb::cube(pos);
+        if !aabb.overlaps_across(&cube, axis) || !is_solid(world, pos) {
+            continue;
+        }
+        if delta > 0. && aabb.max[axis] <= cube.min[axis] + EPSILON {
+            delta = delta.min(cube.min[axis] - aabb.max[axis] - SKIN).max(0.);
+        } else if delta < 0. && aabb.min[axis] >= cube.max[axis] - EPSILON {
+            delta = delta.max(cube.max[axis] - aabb.min[axis] + SKIN).min(0.);
+        }
+    }
+    delta
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cube::{Cube, CubeType};
+
+    const DT: f32 = 1. / 60.;
+    const WIDTH: f32 = 0.6;
+    const HEIGHT: f32 = 1.8;
+
+    // Empty chunks around the origin with a floor whose top is at y = 0.5.
+    fn world() -> World {
+        let mut world = World::with_empty_chunks();
+        fill(&mut world, (-8, 0, -8), (40, 0, 8));
+        world
+    }
+
+    // Fills the box between both corners, inclusive, with solid cubes.
+    fn fill(world: &mut World, min: (i32, i32, i32), max: (i32, i32, i32)) {
+        for x in min.0..=max.0 {
+            for y in min.1..=max.1 {
+                for z in min.2..=max.2 {
+                    world.set_cube(Coord3DI::new(x, y, z), Cube::new(CubeType::DIRT));
+                }
+            }
+        }
+    }
+
+    // Body standing on the floor at `x`.
+    fn standing(world: &World, x: f32) -> Body {
+        let mut body = Body::new(Point3::new(x, 0.5 + SKIN, 0.), WIDTH, HEIGHT);
+        body.step(world, DT);
+        assert!(body.on_ground);
+        body
+    }
+
+    // Walks along +x at `speed` for `steps` steps.
+    fn walk(body: &mut Body, world: &World, speed: f32, steps: usize) {
+        for _ in 0..steps {
+            body.velocity.x = speed;
+            body.step(world, DT);
+        }
+    }
+
+    #[test]
+    fn falls_and_lands_on_the_ground() {
+        let world = world();
+        let mut body = Body::new(Point3::new(0., 5., 0.), WIDTH, HEIGHT);
+        for _ in 0..120 {
+            body.step(&world, DT);
+        }
+        assert!(body.on_ground);
+        assert_eq!(body.velocity.y, 0.);
+        assert!((body.position.y - 0.5).abs() <= 2. * SKIN);
+    }
+
+    #[test]
+    fn stops_at_a_wall() {
+        let mut world = world();
+        fill(&mut world, (3, 1, -2), (3, 3, 2));
+        let mut body = standing(&world, 0.);
+        walk(&mut body, &world, 4., 60);
+        let max_x = body.aabb().max.x;
+        assert!(max_x <= 2.5 && max_x > 2.5 - 2. * SKIN, "{}", max_x);
+        assert_eq!(body.velocity.x, 0.);
+        assert!(body.on_ground);
+    }
+
+    #[test]
+    fn crosses_a_chunk_border() {
+        let mut world = world();
+        // The wall is in the next chunk, the border lies at x = 31.5.
+        fill(&mut world, (36, 1, -2), (36, 3, 2));
+        let mut body = standing(&world, 29.);
+        walk(&mut body, &world, 4., 120);
+        let max_x = body.aabb().max.x;
+        assert!(max_x <= 35.5 && max_x > 35.5 - 2. * SKIN, "{}", max_x);
+        assert!((body.position.y - 0.5).abs() <= 2. * SKIN);
+    }
+
+    #[test]
+    fn steps_up_one_cube() {
+        let mut world = world();
+        fill(&mut world, (3, 1, -2), (8, 1, 2));
+        let mut body = standing(&world, 0.);
+        walk(&mut body, &world, 4., 60);
+        assert!(body.position.x > 3., "{}", body.position.x);
+        assert!((body.position.y - 1.5).abs() <= 2. * SKIN);
+    }
+
+    #[test]
+    fn does_not_step_up_two_cubes() {
+        let mut world = world();
+        fill(&mut world, (3, 1, -2), (8, 2, 2));
+        let mut body = standing(&world, 0.);
+        walk(&mut body, &world, 4., 60);
+        assert!(body.aabb().max.x <= 2.5);
+        assert!((body.position.y - 0.5).abs() <= 2. * SKIN);
+    }
+
+    #[test]
+    fn jumps_into_a_ceiling() {
+        let mut world = world();
+        // The head starts at 2.3, the ceiling is at 2.5.
+        fill(&mut world, (-2, 3, -2), (2, 3, 2));
+        let mut body = standing(&world, 0.);
+        body.jump();
+        let mut highest = 0f32;
+        for _ in 0..60 {
+            body.step(&world, DT);
+            highest = highest.max(body.aabb().max.y);
+        }
+        assert!(highest <= 2.5 && highest > 2.5 - 2. * SKIN, "{}", highest);
+        assert!(body.on_ground);
+    }
+
+    #[test]
+    fn raycast_hits_the_first_solid_cube() {
+        let mut world = world();
+        fill(&mut world, (5, 1, -1), (6, 3, 1));
+        let hit = raycast(&world, Point3::new(0., 2., 0.), Vector3::x(), 10.).unwrap();
+        assert_eq!(hit.cube, Coord3DI::new(5, 2, 0));
+        assert_eq!(hit.previous, Coord3DI::new(4, 2, 0));
+        assert!((hit.distance - 4.5).abs() < EPSILON);
+
+        let hit = raycast(&world, Point3::new(0., 2., 0.), -Vector3::y(), 10.).unwrap();
+        assert_eq!(hit.cube, Coord3DI::new(0, 0, 0));
+        assert_eq!(hit.previous, Coord3DI::new(0, 1, 0));
+        assert!((hit.distance - 1.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn raycast_stops_at_max() {
+        let mut world = world();
+        fill(&mut world, (5, 1, -1), (6, 3, 1));
+        assert!(raycast(&world, Point3::new(0., 2., 0.), Vector3::x(), 4.).is_none());
+        assert!(raycast(&world, Point3::new(0., 2., 0.), Vector3::y(), 10.).is_none());
+    }
+
+    #[test]
+    fn raycast_returns_the_cube_in_front_of_the_face_hit() {
+        // The ray passes below the cube first and enters it from underneath.
+        let mut world = world();
+        fill(&mut world, (2, 2, 0), (2, 2, 0));
+        let direction = Vector3::new(1., 0.1, 0.).normalize();
+        let hit = raycast(&world, Point3::new(0., 1.3, 0.), direction, 10.).unwrap();
+        assert_eq!(hit.cube, Coord3DI::new(2, 2, 0));
+        assert_eq!(hit.previous, Coord3DI::new(2, 1, 0));
+    }
+}
@@ -1,20 +1,170 @@
 use crate::camera::Camera;
-use crate::coordinate::{ChunkCoord3D, Coord3DF};
+use crate::coordinate::{ChunkCoord3D, Coord3DF, Coord3DI};
+use crate::physics::{Aabb, Body};
+use crate::world::World;
+use nalgebra::{Point3, Vector3};
+use std::f32::consts::TAU;
+
+const WIDTH: f32 = 0.6;
+const HEIGHT: f32 = 1.8;
+// Height of the camera above the feet.
+const EYE_HEIGHT: f32 = 1.62;
+const CROUCH_EYE_HEIGHT: f32 = 1.32;
+// Cubes per second.
+const WALK_SPEED: f32 = 4.3;
+const FLY_SPEED: f32 = 24.;
+// How quickly the velocity follows the movement keys, higher is snappier.
+// Stopping uses the same rate, so it doubles as damping.
+const GROUND_ACCELERATION: f32 = 12.;
+const AIR_ACCELERATION: f32 = 2.;
+const FLY_ACCELERATION: f32 = 6.;
+// How quickly the eye moves between standing and crouching height.
+const CROUCH_RATE: f32 = 12.;
+// Distance walked during one up and down of the view bobbing.
+const BOB_STRIDE: f32 = 2.;
+const BOB_HEIGHT: f32 = 0.05;
+// How quickly the bobbing fades in and out when starting and stopping.
+const BOB_FADE_RATE: f32 = 8.;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum MovementMode {
+    /// Falls and collides with the terrain.
+    Walking,
+    /// Moves freely through the terrain.
+    Flying,
+}
 
 pub struct Player {
     pos: Coord3DF,
     pub chunk: ChunkCoord3D,
+    body: Body,
+    pub mode: MovementMode,
+    /// Whether the camera bobs up and down while walking.
+    pub view_bobbing: bool,
+    // Eye position after the last and the step before it.
+    eye: Point3<f32>,
+    previous_eye: Point3<f32>,
+    eye_height: f32,
+    fly_velocity: Vector3<f32>,
+    // Position within the current stride in [0, 1) and strength of the
+    // bobbing in [0, 1].
+    bob_phase: f32,
+    bob_strength: f32,
+    // Vertical offset of the eye from the bobbing after the last and the
+    // step before it.
+    bob_offset: f32,
+    previous_bob_offset: f32,
 }
 
 impl Player {
     pub fn new(camera: &Camera) -> Self {
         let pos = Coord3DF::new(camera.eye.x, camera.eye.y, camera.eye.z);
         let chunk = pos.to_chunk_coord();
-        Self { pos, chunk }
+        let body = Body::new(camera.eye - Vector3::new(0., EYE_HEIGHT, 0.), WIDTH, HEIGHT);
+        Self {
+            pos,
+            chunk,
+            body,
+            mode: MovementMode::Flying,
+            view_bobbing: true,
+            eye: camera.eye,
+            previous_eye: camera.eye,
+            eye_height: EYE_HEIGHT,
+            fly_velocity: Vector3::zeros(),
+            bob_phase: 0.,
+            bob_strength: 0.,
+            bob_offset: 0.,
+            previous_bob_offset: 0.,
+        }
+    }
+
+    /// Advances the simulation by `dt` seconds, moving along the keys
+    /// pressed on the camera's controller.
+    pub fn step(&mut self, world: &World, camera: &Camera, dt: f32) {
+        self.previous_eye = self.eye;
+        self.previous_bob_offset = self.bob_offset;
+        let speed_factor = camera.controller.speed_factor();
+        match self.mode {
+            MovementMode::Flying => {
+                let target = camera.fly_direction() * FLY_SPEED * speed_factor;
+                self.fly_velocity = approach(self.fly_velocity, target, FLY_ACCELERATION, dt);
+                self.eye += self.fly_velocity * dt;
+                self.body.position = self.feet(self.eye);
+                self.update_bobbing(0., dt);
+            }
+            MovementMode::Walking => {
+                let target = camera.walk_direction() * WALK_SPEED * speed_factor;
+                let acceleration = if self.body.on_ground {
+                    GROUND_ACCELERATION
+                } else {
+                    AIR_ACCELERATION
+                };
+                let velocity = approach(self.body.velocity, target, acceleration, dt);
+                self.body.velocity.x = velocity.x;
+                self.body.velocity.z = velocity.z;
+                if camera.jumping() {
+                    self.body.jump();
+                }
+                self.body.step(world, dt);
+
+                let eye_height = if camera.controller.crouching() {
+                    CROUCH_EYE_HEIGHT
+                } else {
+                    EYE_HEIGHT
+                };
+                self.eye_height = approach(self.eye_height, eye_height, CROUCH_RATE, dt);
+                self.eye = self.body.position + Vector3::new(0., self.eye_height, 0.);
+                let speed = if self.body.on_ground {
+                    self.body.velocity.xz().norm()
+                } else {
+                    0.
+                };
+                self.update_bobbing(speed, dt);
+            }
+        }
+        self.pos = Coord3DF::new(self.eye.x, self.eye.y, self.eye.z);
+    }
+
+    // Advances the bobbing for moving at `speed` on the ground.
+    fn update_bobbing(&mut self, speed: f32, dt: f32) {
+        let strength = if self.view_bobbing {
+            (speed / WALK_SPEED).min(1.)
+        } else {
+            0.
+        };
+        self.bob_strength = approach(self.bob_strength, strength, BOB_FADE_RATE, dt);
+        self.bob_phase = (self.bob_phase + speed * dt / BOB_STRIDE).fract();
+        self.bob_offset = (self.bob_phase * TAU).sin() * BOB_HEIGHT * self.bob_strength;
+    }
+
+    /// Eye position `alpha` of the way from the previous to the last step,
+    /// including the view bobbing.
+    pub fn eye(&self, alpha: f32) -> Point3<f32> {
+        let bob = self.previous_bob_offset + (self.bob_offset - self.previous_bob_offset) * alpha;
+        self.previous_eye + (self.eye - self.previous_eye) * alpha + Vector3::new(0., bob, 0.)
+    }
+
+    /// Switches between walking and flying. Walking starts at rest.
+    pub fn toggle_mode(&mut self) {
+        self.mode = match self.mode {
+            MovementMode::Walking => MovementMode::Flying,
+            MovementMode::Flying => MovementMode::Walking,
+        };
+        self.body = Body::new(self.feet(self.eye), WIDTH, HEIGHT);
+        self.fly_velocity = Vector3::zeros();
+    }
+
+    /// Moves the eye to `eye` without passing the positions in between.
+    pub fn teleport(&mut self, eye: Point3<f32>) {
+        self.eye = eye;
+        self.previous_eye = eye;
+        self.body.position = self.feet(eye);
+        self.pos = Coord3DF::new(eye.x, eye.y, eye.z);
     }
 
-    pub fn update_pos(&mut self, camera: &Camera) {
-        self.pos = Coord3DF::new(camera.eye.x, camera.eye.y, camera.eye.z);
+    /// Whether the cube at `pos` would overlap the player's body.
+    pub fn collides_with(&self, pos: Coord3DI) -> bool {
+        self.body.aabb().intersects(&Aabb::cube(pos))
     }
 
     pub fn update_chunk_pos(&mut self) {
@@ -25,4 +175,20 @@ impl Player {
         let result = self.chunk != self.pos.to_chunk_coord();
         result
     }
+
+    fn feet(&self, eye: Point3<f32>) -> Point3<f32> {
+        eye - Vector3::new(0., self.eye_height, 0.)
+    }
+}
+
+// Moves `value` towards `target`, closing the same share of the distance in
+// equally long times regardless of the step length.
+fn approach<T>(value: T, target: T, rate: f32, dt: f32) -> T
+where
+    T: Copy
+        + std::ops::Add<Output = T>
+        + std::ops::Sub<Output = T>
+        + std::ops::Mul<f32, Output = T>,
+{
+    value + (target - value) * (1. - (-rate * dt).exp())
 }
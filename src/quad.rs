@@ -1,17 +1,22 @@
-use crate::coordinate::Coord3DI;
+use crate::coordinate::Coord3DF;
 use crate::renderer::instance::InstanceRaw;
 use crate::renderer::vertex::Vertex;
-use nalgebra::{Rotation3, Translation3};
+use nalgebra::{Matrix4, Rotation3, Translation3, Vector3};
 
 #[derive(Debug)]
 pub struct Quad {
     pub position: Translation3<f32>,
     rotation: Rotation3<f32>,
     texture_index: u32,
+    scale: f32,
+    // Share of the edge length a side face covers from its bottom edge up.
+    height: f32,
 }
 
 impl Quad {
-    pub fn new(pos: Coord3DI, facing: Rotation, texture_index: u32) -> Self {
+    /// Creates a face of a cube with the given edge length centered at
+    /// `pos`. Downsampled chunk meshes use cubes larger than 1.
+    pub fn new(pos: Coord3DF, facing: Rotation, texture_index: u32, scale: f32) -> Self {
         let rotation: Rotation3<f32>;
         match facing {
             Rotation::UP => {
@@ -39,14 +44,36 @@ impl Quad {
             }
         }
         Quad {
-            position: Translation3::from([pos.x as f32, pos.y as f32, pos.z as f32]),
+            position: Translation3::from([pos.x, pos.y, pos.z]),
             rotation,
             texture_index,
+            scale,
+            height: 1.,
         }
     }
+
+    /// Moves the face down by `offset` times its edge length.
+    pub fn lowered(mut self, offset: f32) -> Self {
+        self.position.y -= offset * self.scale;
+        self
+    }
+
+    /// Shrinks a side face to `height` times its edge length, keeping its
+    /// bottom edge in place. Used for the sides of fluids which aren't full.
+    pub fn with_height(mut self, height: f32) -> Self {
+        self.height = height;
+        self
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
-        let t_matrix: [[f32; 4]; 4] =
-            (self.position.to_homogeneous() * self.rotation.matrix().to_homogeneous()).into();
+        // Side faces keep their local y axis pointing up after the rotation.
+        let shrink = Matrix4::new_translation(&Vector3::new(0., (self.height - 1.) / 2., 0.))
+            * Matrix4::new_nonuniform_scaling(&Vector3::new(1., self.height, 1.));
+        let t_matrix: [[f32; 4]; 4] = (self.position.to_homogeneous()
+            * Matrix4::new_scaling(self.scale)
+            * self.rotation.matrix().to_homogeneous()
+            * shrink)
+            .into();
         InstanceRaw {
             t_matrix,
             texture_index: self.texture_index,
@@ -54,6 +81,7 @@ impl Quad {
     }
 }
 
+#[derive(Copy, Clone)]
 pub enum Rotation {
     UP,
     DOWN,
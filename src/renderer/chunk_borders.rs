@@ -0,0 +1,101 @@
+use super::graphics::Graphics;
+use crate::coordinate::ChunkCoord3D;
+use crate::uniform::{RenderPassData, SetUniforms};
+use crate::world::CHUNK_I32;
+use hashbrown::HashSet;
+use wgpu::util::DeviceExt;
+
+// Corners of a unit box, the 12 edges index into it.
+const CORNERS: [[f32; 3]; 8] = [
+    [0., 0., 0.],
+    [1., 0., 0.],
+    [1., 0., 1.],
+    [0., 0., 1.],
+    [0., 1., 0.],
+    [1., 1., 0.],
+    [1., 1., 1.],
+    [0., 1., 1.],
+];
+const EDGES: [(usize, usize); 12] = [
+    (0, 1),
+    (1, 2),
+    (2, 3),
+    (3, 0),
+    (4, 5),
+    (5, 6),
+    (6, 7),
+    (7, 4),
+    (0, 4),
+    (1, 5),
+    (2, 6),
+    (3, 7),
+];
+
+/// Line boxes around chunks, rebuilt when the chunks change.
+pub struct ChunkBorders {
+    buffer: wgpu::Buffer,
+    vertices_len: u32,
+    chunks: HashSet<ChunkCoord3D>,
+}
+
+impl ChunkBorders {
+    pub fn new<'a>(graphics: &Graphics, chunks: impl Iterator<Item = &'a ChunkCoord3D>) -> Self {
+        let chunks: HashSet<ChunkCoord3D> = chunks.copied().collect();
+        let size = CHUNK_I32 as f32;
+        let mut vertices: Vec<[f32; 3]> = Vec::new();
+        for chunk in chunks.iter() {
+            // Cubes are centred on integer coordinates.
+            let origin = chunk.to_world_position_f32();
+            let corner = |i: usize| {
+                [
+                    origin.x - 0.5 + CORNERS[i][0] * size,
+                    origin.y - 0.5 + CORNERS[i][1] * size,
+                    origin.z - 0.5 + CORNERS[i][2] * size,
+                ]
+            };
+            for (a, b) in EDGES.iter() {
+                vertices.push(corner(*a));
+                vertices.push(corner(*b));
+            }
+        }
+        let buffer = graphics
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("chunk border vertex buffer"),
+                contents: bytemuck::cast_slice(&vertices),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+        Self {
+            buffer,
+            vertices_len: vertices.len() as u32,
+            chunks,
+        }
+    }
+
+    /// Whether the boxes are around exactly `chunks`.
+    pub fn matches<'a>(&self, mut chunks: impl ExactSizeIterator<Item = &'a ChunkCoord3D>) -> bool {
+        chunks.len() == self.chunks.len() && chunks.all(|c| self.chunks.contains(c))
+    }
+
+    pub fn init_buffer_layout<'a>() -> wgpu::VertexBufferLayout<'a> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<[f32; 3]>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &[wgpu::VertexAttribute {
+                format: wgpu::VertexFormat::Float32x3,
+                offset: 0,
+                shader_location: 0,
+            }],
+        }
+    }
+
+    /// Draws the lines with the pipeline already set on the pass.
+    pub fn render<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, uniform: &'a RenderPassData) {
+        if self.vertices_len == 0 {
+            return;
+        }
+        pass.set_vertex_buffer(0, self.buffer.slice(..));
+        pass.set_bind_groups(&uniform);
+        pass.draw(0..self.vertices_len, 0..1);
+    }
+}
@@ -0,0 +1,263 @@
+use super::graphics::Graphics;
+use super::indirect::DrawIndexedIndirect;
+use crate::frustum_culling::Frustum;
+use crate::world::CHUNK_USIZE;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+pub struct ChunkBounds {
+    pub origin: [f32; 4],
+    pub base_instance: u32,
+    pub instance_count: u32,
+    pub visible: u32,
+    _padding: u32,
+}
+
+impl ChunkBounds {
+    pub fn new(origin: [f32; 3], base_instance: u32, instance_count: u32) -> Self {
+        Self {
+            origin: [origin[0], origin[1], origin[2], 1.],
+            base_instance,
+            instance_count,
+            visible: 1,
+            _padding: 0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct CullData {
+    planes: [[f32; 4]; 6],
+    chunk_count: u32,
+    indices_len: u32,
+    chunk_size: f32,
+    _padding: u32,
+}
+
+/// Frustum culls chunk bounding boxes in a compute shader and writes a
+/// compacted list of indexed indirect draws for the visible chunks.
+pub struct GpuCulling {
+    clear_pipeline: wgpu::ComputePipeline,
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    bind_group: Option<wgpu::BindGroup>,
+    chunk_buffer: Option<wgpu::Buffer>,
+    cull_buffer: wgpu::Buffer,
+    count_buffer: wgpu::Buffer,
+    draw_buffer: Option<wgpu::Buffer>,
+    // Number of chunks the buffers have room for.
+    capacity: usize,
+    chunk_count: u32,
+}
+
+impl GpuCulling {
+    const WORKGROUP_SIZE: u32 = 64;
+
+    pub fn new(graphics: &Graphics) -> Self {
+        let shader = graphics
+            .device
+            .create_shader_module(&wgpu::include_wgsl!("../shaders/cull.wgsl"));
+        let bind_group_layout =
+            graphics
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("culling bind group layout"),
+                    entries: &[
+                        GpuCulling::layout_entry(0, wgpu::BufferBindingType::Uniform),
+                        GpuCulling::layout_entry(
+                            1,
+                            wgpu::BufferBindingType::Storage { read_only: true },
+                        ),
+                        GpuCulling::layout_entry(
+                            2,
+                            wgpu::BufferBindingType::Storage { read_only: false },
+                        ),
+                        GpuCulling::layout_entry(
+                            3,
+                            wgpu::BufferBindingType::Storage { read_only: false },
+                        ),
+                    ],
+                });
+        let layout = graphics
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("culling pipeline layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let clear_pipeline =
+            graphics
+                .device
+                .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                    label: Some("culling clear compute pipeline"),
+                    layout: Some(&layout),
+                    module: &shader,
+                    entry_point: "clear",
+                });
+        let pipeline = graphics
+            .device
+            .create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some("culling compute pipeline"),
+                layout: Some(&layout),
+                module: &shader,
+                entry_point: "main",
+            });
+        let cull_buffer = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("culling data buffer"),
+            size: std::mem::size_of::<CullData>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let count_buffer = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("culling draw count buffer"),
+            size: std::mem::size_of::<u32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        Self {
+            clear_pipeline,
+            pipeline,
+            bind_group_layout,
+            bind_group: None,
+            chunk_buffer: None,
+            cull_buffer,
+            count_buffer,
+            draw_buffer: None,
+            capacity: 0,
+            chunk_count: 0,
+        }
+    }
+
+    /// Uploads the bounds of all packed chunks. The buffers only grow, so
+    /// they are only recreated when there are more chunks than ever before.
+    pub fn upload(&mut self, graphics: &Graphics, bounds: &[ChunkBounds]) {
+        self.chunk_count = bounds.len() as u32;
+        if bounds.is_empty() {
+            return;
+        }
+        if bounds.len() > self.capacity {
+            self.create_buffers(graphics, bounds.len().next_power_of_two());
+        }
+        if let Some(chunk_buffer) = &self.chunk_buffer {
+            graphics
+                .queue
+                .write_buffer(chunk_buffer, 0, bytemuck::cast_slice(bounds));
+        }
+    }
+
+    fn create_buffers(&mut self, graphics: &Graphics, capacity: usize) {
+        let chunk_buffer = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("chunk bounds buffer"),
+            size: (capacity * std::mem::size_of::<ChunkBounds>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let draw_buffer = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("culled indirect draw buffer"),
+            size: (capacity * std::mem::size_of::<DrawIndexedIndirect>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            mapped_at_creation: false,
+        });
+        let bind_group = graphics
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("culling bind group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: self.cull_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: chunk_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: draw_buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: self.count_buffer.as_entire_binding(),
+                    },
+                ],
+            });
+        self.bind_group = Some(bind_group);
+        self.chunk_buffer = Some(chunk_buffer);
+        self.draw_buffer = Some(draw_buffer);
+        self.capacity = capacity;
+    }
+
+    pub fn dispatch(&self, graphics: &Graphics, frustum: &Frustum, indices_len: u32) {
+        let bind_group = match &self.bind_group {
+            Some(b) if self.chunk_count > 0 => b,
+            _ => return,
+        };
+        let data = CullData {
+            planes: frustum.planes(),
+            chunk_count: self.chunk_count,
+            indices_len,
+            chunk_size: CHUNK_USIZE as f32,
+            _padding: 0,
+        };
+        graphics
+            .queue
+            .write_buffer(&self.cull_buffer, 0, bytemuck::cast_slice(&[data]));
+
+        let mut encoder = graphics
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("culling command encoder"),
+            });
+        let groups =
+            (self.chunk_count + GpuCulling::WORKGROUP_SIZE - 1) / GpuCulling::WORKGROUP_SIZE;
+        // Separate passes, so the clear is finished before the culling
+        // starts appending draws.
+        for (pipeline, label) in [
+            (&self.clear_pipeline, "culling clear compute pass"),
+            (&self.pipeline, "culling compute pass"),
+        ] {
+            let mut pass =
+                encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some(label) });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch(groups, 1, 1);
+        }
+        graphics.queue.submit(Some(encoder.finish()));
+    }
+
+    pub fn render<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, count_supported: bool) {
+        if let Some(draw_buffer) = &self.draw_buffer {
+            if self.chunk_count == 0 {
+                return;
+            }
+            if count_supported {
+                pass.multi_draw_indexed_indirect_count(
+                    draw_buffer,
+                    0,
+                    &self.count_buffer,
+                    0,
+                    self.chunk_count,
+                );
+            } else {
+                pass.multi_draw_indexed_indirect(draw_buffer, 0, self.chunk_count);
+            }
+        }
+    }
+
+    fn layout_entry(binding: u32, ty: wgpu::BufferBindingType) -> wgpu::BindGroupLayoutEntry {
+        wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }
+    }
+}
@@ -1,14 +1,28 @@
+use super::readback::Readback;
+use anyhow::{anyhow, Result};
 use futures::executor::block_on;
 use std::sync::Arc;
 
+// Format of the offscreen target when rendering without a window.
+const HEADLESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
 pub struct Graphics {
     pub size: winit::dpi::PhysicalSize<u32>,
-    pub surface: wgpu::Surface,
+    // `None` when rendering headless into `offscreen`.
+    surface: Option<wgpu::Surface>,
+    offscreen: Option<wgpu::Texture>,
     pub device: Arc<wgpu::Device>,
     pub queue: wgpu::Queue,
     pub surface_config: wgpu::SurfaceConfiguration,
 }
 
+/// Texture a frame is rendered into. Frames of the window surface are
+/// presented when dropped.
+pub struct Frame {
+    pub view: wgpu::TextureView,
+    _surface_texture: Option<wgpu::SurfaceTexture>,
+}
+
 impl Graphics {
     pub fn new(window: &winit::window::Window) -> Self {
         let backend = wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY);
@@ -23,21 +37,7 @@ impl Graphics {
             compatible_surface: Some(&surface),
         }))
         .unwrap();
-
-        let adapter_info = adapter.get_info();
-        println!("Using {} ({:?})", adapter_info.name, adapter_info.backend);
-
-        let (device, queue) = block_on(adapter.request_device(
-            &wgpu::DeviceDescriptor {
-                label: Some("Device"),
-                features: wgpu::Features::TEXTURE_BINDING_ARRAY
-                    | wgpu::Features::NON_FILL_POLYGON_MODE
-                    | wgpu::Features::SPIRV_SHADER_PASSTHROUGH,
-                limits: adapter.limits(),
-            },
-            None,
-        ))
-        .unwrap();
+        let (device, queue) = Graphics::request_device(&adapter).unwrap();
 
         let surface_config = wgpu::SurfaceConfiguration {
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
@@ -50,17 +50,132 @@ impl Graphics {
         let device_ref = Arc::new(device);
         Self {
             size,
-            surface,
+            surface: Some(surface),
+            offscreen: None,
             device: device_ref,
             queue,
             surface_config,
         }
     }
 
+    /// Renders into an offscreen texture instead of a window, which can be
+    /// read back with `read_frame`. Works with software adapters like
+    /// lavapipe, selected with the `WGPU_BACKEND` environment variable.
+    pub fn new_headless(width: u32, height: u32) -> Result<Self> {
+        let backend = wgpu::util::backend_bits_from_env().unwrap_or(wgpu::Backends::PRIMARY);
+        let instance = wgpu::Instance::new(backend);
+        let adapter = block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            compatible_surface: None,
+        }))
+        .ok_or_else(|| anyhow!("No graphics adapter found"))?;
+        let (device, queue) = Graphics::request_device(&adapter)?;
+
+        let surface_config = wgpu::SurfaceConfiguration {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            format: HEADLESS_FORMAT,
+            width: width.max(1),
+            height: height.max(1),
+            present_mode: wgpu::PresentMode::Fifo,
+        };
+        let mut graphics = Self {
+            size: winit::dpi::PhysicalSize::new(width, height),
+            surface: None,
+            offscreen: None,
+            device: Arc::new(device),
+            queue,
+            surface_config,
+        };
+        graphics.offscreen = Some(graphics.create_offscreen_texture());
+        Ok(graphics)
+    }
+
+    fn request_device(adapter: &wgpu::Adapter) -> Result<(wgpu::Device, wgpu::Queue)> {
+        let adapter_info = adapter.get_info();
+        println!("Using {} ({:?})", adapter_info.name, adapter_info.backend);
+
+        let device = block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("Device"),
+                // All optional, chunks are drawn one by one without multi
+                // draw and the wireframe view is left out without line
+                // polygons.
+                features: adapter.features()
+                    & (wgpu::Features::MULTI_DRAW_INDIRECT
+                        | wgpu::Features::MULTI_DRAW_INDIRECT_COUNT
+                        | wgpu::Features::NON_FILL_POLYGON_MODE),
+                limits: adapter.limits(),
+            },
+            None,
+        ))?;
+        Ok(device)
+    }
+
+    fn create_offscreen_texture(&self) -> wgpu::Texture {
+        self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("offscreen frame texture"),
+            size: wgpu::Extent3d {
+                width: self.surface_config.width,
+                height: self.surface_config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: self.surface_config.format,
+            usage: self.surface_config.usage,
+        })
+    }
+
+    pub fn current_frame(&self) -> Result<Frame, wgpu::SurfaceError> {
+        match (&self.surface, &self.offscreen) {
+            (Some(surface), _) => {
+                let surface_texture = surface.get_current_frame()?.output;
+                let view = surface_texture
+                    .texture
+                    .create_view(&wgpu::TextureViewDescriptor::default());
+                Ok(Frame {
+                    view,
+                    _surface_texture: Some(surface_texture),
+                })
+            }
+            (None, Some(texture)) => Ok(Frame {
+                view: texture.create_view(&wgpu::TextureViewDescriptor::default()),
+                _surface_texture: None,
+            }),
+            (None, None) => Err(wgpu::SurfaceError::Lost),
+        }
+    }
+
+    /// Copies the last frame rendered headless back to the CPU.
+    pub fn read_frame(&self) -> Result<image::RgbaImage> {
+        let texture = self
+            .offscreen
+            .as_ref()
+            .ok_or_else(|| anyhow!("Only headless frames can be read back"))?;
+        let readback = Readback::new(
+            &self,
+            self.surface_config.width,
+            self.surface_config.height,
+            self.surface_config.format,
+        );
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("frame readback encoder"),
+            });
+        readback.copy_from(&mut encoder, texture);
+        self.queue.submit(Some(encoder.finish()));
+        readback.map().wait(&self)
+    }
+
     pub fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         self.size = new_size;
         self.surface_config.width = self.size.width.max(1);
         self.surface_config.height = self.size.height.max(1);
-        self.surface.configure(&self.device, &self.surface_config);
+        match &self.surface {
+            Some(surface) => surface.configure(&self.device, &self.surface_config),
+            None => self.offscreen = Some(self.create_offscreen_texture()),
+        }
     }
 }
@@ -0,0 +1,305 @@
+use super::culling::{ChunkBounds, GpuCulling};
+use super::graphics::Graphics;
+use super::instance::InstanceRaw;
+use crate::chunk::ChunkMesh;
+use crate::coordinate::ChunkCoord3D;
+use crate::frustum_culling::Frustum;
+use crate::uniform::{RenderPassData, SetUniforms};
+use hashbrown::{HashMap, HashSet};
+use std::ops::Range;
+
+/// Layout of a single indexed draw command expected by
+/// `RenderPass::multi_draw_indexed_indirect`.
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+pub struct DrawIndexedIndirect {
+    pub vertex_count: u32,
+    pub instance_count: u32,
+    pub base_index: u32,
+    pub vertex_offset: i32,
+    pub base_instance: u32,
+}
+
+/// Packs every chunk mesh into one shared instance buffer and draws all
+/// visible chunks with a single `multi_draw_indexed_indirect` call. The
+/// draw list is culled and compacted on the GPU by [`GpuCulling`]. When
+/// meshes change only their ranges of the buffer are rewritten.
+pub struct IndirectRenderer {
+    instance_buffer: Option<wgpu::Buffer>,
+    // Size of the instance buffer in instances.
+    capacity: u32,
+    allocator: RangeAllocator,
+    // Index into `positions` and `bounds` and the instance range of every
+    // packed chunk.
+    slots: HashMap<ChunkCoord3D, (usize, Range<u32>)>,
+    positions: Vec<ChunkCoord3D>,
+    bounds: Vec<ChunkBounds>,
+    culling: GpuCulling,
+    count_supported: bool,
+    // Chunks whose mesh was added, replaced or removed since the last frame.
+    changed: HashSet<ChunkCoord3D>,
+}
+
+impl IndirectRenderer {
+    pub fn is_supported(graphics: &Graphics) -> bool {
+        graphics
+            .device
+            .features()
+            .contains(wgpu::Features::MULTI_DRAW_INDIRECT)
+    }
+
+    pub fn new(graphics: &Graphics) -> Self {
+        let culling = GpuCulling::new(&graphics);
+        let count_supported = graphics
+            .device
+            .features()
+            .contains(wgpu::Features::MULTI_DRAW_INDIRECT_COUNT);
+        Self {
+            instance_buffer: None,
+            capacity: 0,
+            allocator: RangeAllocator::default(),
+            slots: HashMap::new(),
+            positions: Vec::new(),
+            bounds: Vec::new(),
+            culling,
+            count_supported,
+            changed: HashSet::new(),
+        }
+    }
+
+    /// Marks the packed instances of the chunk at `pos` as outdated. Has to
+    /// be called whenever its mesh is added, replaced or removed.
+    pub fn invalidate(&mut self, pos: ChunkCoord3D) {
+        self.changed.insert(pos);
+    }
+
+    pub fn prepare(
+        &mut self,
+        graphics: &Graphics,
+        meshes: &HashMap<ChunkCoord3D, ChunkMesh>,
+        frustum: &Frustum,
+        indices_len: u32,
+        visible: Option<&HashSet<ChunkCoord3D>>,
+    ) {
+        let mut changed = !self.changed.is_empty();
+        if changed {
+            self.pack_changes(&graphics, meshes);
+        }
+        for (p, b) in self.positions.iter().zip(self.bounds.iter_mut()) {
+            let flag = visible.map_or(true, |v| v.contains(p)) as u32;
+            if b.visible != flag {
+                b.visible = flag;
+                changed = true;
+            }
+        }
+        if changed {
+            self.culling.upload(&graphics, &self.bounds);
+        }
+        self.culling.dispatch(&graphics, &frustum, indices_len);
+    }
+
+    pub fn render<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, render_data: &'a RenderPassData) {
+        if let Some(instance_buffer) = &self.instance_buffer {
+            pass.set_vertex_buffer(0, render_data.face_vertex_buffer.slice(..));
+            pass.set_vertex_buffer(1, instance_buffer.slice(..));
+            pass.set_index_buffer(
+                render_data.face_index_buffer.slice(..),
+                wgpu::IndexFormat::Uint32,
+            );
+            pass.set_bind_groups(&render_data);
+            self.culling.render(pass, self.count_supported);
+        }
+    }
+
+    // Frees the ranges of all changed chunks, then copies their new
+    // instance buffers into newly allocated ranges of the shared buffer.
+    fn pack_changes(&mut self, graphics: &Graphics, meshes: &HashMap<ChunkCoord3D, ChunkMesh>) {
+        let changed = std::mem::take(&mut self.changed);
+        for p in changed.iter() {
+            self.remove(p);
+        }
+        let added = changed
+            .iter()
+            .filter_map(|p| meshes.get(p).map(|m| (*p, m)))
+            .filter(|(_, m)| m.instances_len > 0)
+            .collect::<Vec<_>>();
+        for (p, m) in added.iter() {
+            let range = self.allocator.allocate(m.instances_len as u32);
+            let origin = p.to_world_position_f32();
+            self.slots.insert(*p, (self.bounds.len(), range.clone()));
+            self.positions.push(*p);
+            self.bounds.push(ChunkBounds::new(
+                [origin.x, origin.y, origin.z],
+                range.start,
+                m.instances_len as u32,
+            ));
+        }
+        if self.allocator.end > self.capacity {
+            self.grow(&graphics, self.allocator.end);
+        }
+        let instance_buffer = match &self.instance_buffer {
+            Some(b) => b,
+            None => return,
+        };
+
+        let stride = std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress;
+        let mut encoder = graphics
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("mesh packing command encoder"),
+            });
+        for (p, m) in added.iter() {
+            let start = self.slots[p].1.start as wgpu::BufferAddress;
+            encoder.copy_buffer_to_buffer(
+                &m.instance_buffer,
+                0,
+                instance_buffer,
+                start * stride,
+                m.instances_len as wgpu::BufferAddress * stride,
+            );
+        }
+        graphics.queue.submit(Some(encoder.finish()));
+    }
+
+    fn remove(&mut self, pos: &ChunkCoord3D) {
+        if let Some((index, range)) = self.slots.remove(pos) {
+            self.allocator.free(range);
+            self.positions.swap_remove(index);
+            self.bounds.swap_remove(index);
+            if let Some(moved) = self.positions.get(index) {
+                self.slots.get_mut(moved).unwrap().0 = index;
+            }
+        }
+    }
+
+    // Replaces the instance buffer by one with room for at least `needed`
+    // instances and copies the packed instances over.
+    fn grow(&mut self, graphics: &Graphics, needed: u32) {
+        let capacity = needed.max(self.capacity * 2);
+        let stride = std::mem::size_of::<InstanceRaw>() as wgpu::BufferAddress;
+        let instance_buffer = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("packed chunk instance buffer"),
+            size: capacity as wgpu::BufferAddress * stride,
+            usage: wgpu::BufferUsages::VERTEX
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        if let Some(old) = &self.instance_buffer {
+            let mut encoder =
+                graphics
+                    .device
+                    .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                        label: Some("instance buffer grow command encoder"),
+                    });
+            encoder.copy_buffer_to_buffer(
+                old,
+                0,
+                &instance_buffer,
+                0,
+                self.capacity as wgpu::BufferAddress * stride,
+            );
+            graphics.queue.submit(Some(encoder.finish()));
+        }
+        self.instance_buffer = Some(instance_buffer);
+        self.capacity = capacity;
+    }
+}
+
+// First fit allocator for instance ranges of the shared buffer.
+#[derive(Default)]
+struct RangeAllocator {
+    // Free ranges below `end`, sorted and never touching each other.
+    free: Vec<Range<u32>>,
+    // Everything from here on is free.
+    end: u32,
+}
+
+impl RangeAllocator {
+    fn allocate(&mut self, len: u32) -> Range<u32> {
+        if let Some(i) = self.free.iter().position(|r| r.end - r.start >= len) {
+            let start = self.free[i].start;
+            self.free[i].start += len;
+            if self.free[i].is_empty() {
+                self.free.remove(i);
+            }
+            return start..start + len;
+        }
+        let start = self.end;
+        self.end += len;
+        start..self.end
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        let mut i = self
+            .free
+            .iter()
+            .position(|r| r.start > range.start)
+            .unwrap_or(self.free.len());
+        self.free.insert(i, range);
+        if i + 1 < self.free.len() && self.free[i].end == self.free[i + 1].start {
+            self.free[i].end = self.free[i + 1].end;
+            self.free.remove(i + 1);
+        }
+        if i > 0 && self.free[i - 1].end == self.free[i].start {
+            self.free[i - 1].end = self.free[i].end;
+            self.free.remove(i);
+            i -= 1;
+        }
+        if self.free[i].end == self.end {
+            self.end = self.free[i].start;
+            self.free.remove(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn freed_range_is_reused() {
+        let mut allocator = RangeAllocator::default();
+        let a = allocator.allocate(10);
+        allocator.allocate(10);
+        allocator.free(a);
+        assert_eq!(allocator.allocate(4), 0..4);
+        assert_eq!(allocator.allocate(6), 4..10);
+        assert_eq!(allocator.end, 20);
+    }
+
+    #[test]
+    fn too_small_range_is_skipped() {
+        let mut allocator = RangeAllocator::default();
+        let a = allocator.allocate(4);
+        allocator.allocate(10);
+        allocator.free(a);
+        assert_eq!(allocator.allocate(5), 14..19);
+    }
+
+    #[test]
+    fn neighbouring_ranges_are_merged() {
+        let mut allocator = RangeAllocator::default();
+        let a = allocator.allocate(4);
+        let b = allocator.allocate(4);
+        let c = allocator.allocate(4);
+        allocator.allocate(4);
+        allocator.free(a);
+        allocator.free(c);
+        allocator.free(b);
+        assert_eq!(allocator.free, vec![0..12]);
+        assert_eq!(allocator.allocate(12), 0..12);
+    }
+
+    #[test]
+    fn free_range_at_the_end_shrinks_it() {
+        let mut allocator = RangeAllocator::default();
+        allocator.allocate(4);
+        let b = allocator.allocate(4);
+        let c = allocator.allocate(4);
+        allocator.free(b);
+        allocator.free(c);
+        assert!(allocator.free.is_empty());
+        assert_eq!(allocator.end, 4);
+    }
+}
@@ -1,5 +1,12 @@
+pub mod chunk_borders;
+pub mod culling;
 pub mod graphics;
+pub mod indirect;
 pub mod instance;
 pub mod pipeline;
+pub mod post;
+pub mod readback;
 pub mod renderer;
+pub mod shadow;
+pub mod sky;
 pub mod vertex;
@@ -1,62 +1,165 @@
+use super::chunk_borders::ChunkBorders;
 use super::graphics::Graphics;
 use super::instance::InstanceRaw;
+use super::post::HDR_FORMAT;
 use super::vertex::Vertex;
 use crate::texture::Texture;
 use crate::uniform::RenderPassData;
+use std::borrow::Cow;
 
 pub struct Pipeline {
     pub pipeline: wgpu::RenderPipeline,
 }
 
+/// WGSL sources of the block and debug pipelines. Embedded at build time
+/// and replaced by the files on disk when they are hot reloaded.
+#[derive(Clone)]
+pub struct ShaderSources {
+    block: Cow<'static, str>,
+    debug: Cow<'static, str>,
+}
+
+impl ShaderSources {
+    pub fn new() -> Self {
+        Self {
+            block: Cow::Borrowed(include_str!("../shaders/block.wgsl")),
+            debug: Cow::Borrowed(include_str!("../shaders/debug.wgsl")),
+        }
+    }
+
+    /// Replaces the source of a shader file, returns false if no pipeline
+    /// in `Renderer::pipelines` is built from it.
+    pub fn set(&mut self, file_name: &str, source: String) -> bool {
+        match file_name {
+            "block.wgsl" => self.block = Cow::Owned(source),
+            "debug.wgsl" => self.debug = Cow::Owned(source),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Compiles the block and debug shader modules.
+    pub fn create_modules(&self, graphics: &Graphics) -> (wgpu::ShaderModule, wgpu::ShaderModule) {
+        let module = |label, source: &Cow<'static, str>| {
+            graphics
+                .device
+                .create_shader_module(&wgpu::ShaderModuleDescriptor {
+                    label: Some(label),
+                    source: wgpu::ShaderSource::Wgsl(source.clone()),
+                })
+        };
+        (
+            module("block.wgsl", &self.block),
+            module("debug.wgsl", &self.debug),
+        )
+    }
+}
+
+// Rasterization and blending of a pipeline, everything else is shared.
+struct Style {
+    topology: wgpu::PrimitiveTopology,
+    polygon_mode: wgpu::PolygonMode,
+    cull_mode: Option<wgpu::Face>,
+    depth_write: bool,
+    depth_compare: wgpu::CompareFunction,
+    blend: wgpu::BlendState,
+}
+
+impl Style {
+    fn new(view: &Type) -> Self {
+        let mut style = Self {
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            polygon_mode: wgpu::PolygonMode::Fill,
+            cull_mode: Some(wgpu::Face::Back),
+            depth_write: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            blend: wgpu::BlendState::REPLACE,
+        };
+        match view {
+            Type::Main | Type::Normals | Type::ChunkTint => (),
+            // Translucent surfaces like water are seen from both sides.
+            Type::Translucent => {
+                style.cull_mode = None;
+                style.depth_write = false;
+                style.blend = wgpu::BlendState::ALPHA_BLENDING;
+            }
+            Type::Wireframe => {
+                style.polygon_mode = wgpu::PolygonMode::Line;
+                style.cull_mode = None;
+            }
+            // Every face is drawn and adds to the color below it.
+            Type::Overdraw => {
+                style.cull_mode = None;
+                style.depth_write = false;
+                style.depth_compare = wgpu::CompareFunction::Always;
+                style.blend = wgpu::BlendState {
+                    color: wgpu::BlendComponent {
+                        src_factor: wgpu::BlendFactor::One,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha: wgpu::BlendComponent::REPLACE,
+                };
+            }
+            Type::ChunkBorders => {
+                style.topology = wgpu::PrimitiveTopology::LineList;
+                style.cull_mode = None;
+                style.depth_write = false;
+            }
+        }
+        style
+    }
+}
+
 impl Pipeline {
     fn new(
         graphics: &Graphics,
         label: &str,
-        v_shader: &str,
-        f_shader: &str,
+        vertex: (&wgpu::ShaderModule, &str),
+        fragment: (&wgpu::ShaderModule, &str),
         vertex_layout: Vec<wgpu::VertexBufferLayout>,
         layout: Option<&wgpu::PipelineLayout>,
         depth_format: Option<wgpu::TextureFormat>,
+        style: Style,
+        sample_count: u32,
     ) -> Self {
-        let vertex_shader = Pipeline::load_shader(&graphics, v_shader);
-        let fragment_shader = Pipeline::load_shader(&graphics, f_shader);
         let pipeline = graphics
             .device
             .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
                 label: Some(&format!("{} render pipeline", label)),
                 layout,
                 vertex: wgpu::VertexState {
-                    module: &vertex_shader,
-                    entry_point: "main",
+                    module: vertex.0,
+                    entry_point: vertex.1,
                     buffers: &vertex_layout,
                 },
                 primitive: wgpu::PrimitiveState {
-                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    topology: style.topology,
                     strip_index_format: None,
                     front_face: wgpu::FrontFace::Ccw,
-                    cull_mode: Some(wgpu::Face::Back),
+                    cull_mode: style.cull_mode,
                     clamp_depth: false,
-                    polygon_mode: wgpu::PolygonMode::Fill,
+                    polygon_mode: style.polygon_mode,
                     conservative: false,
                 },
                 depth_stencil: depth_format.map(|f| wgpu::DepthStencilState {
                     format: f,
-                    depth_write_enabled: true,
-                    depth_compare: wgpu::CompareFunction::Less,
+                    depth_write_enabled: style.depth_write,
+                    depth_compare: style.depth_compare,
                     stencil: wgpu::StencilState::default(),
                     bias: wgpu::DepthBiasState::default(),
                 }),
                 multisample: wgpu::MultisampleState {
-                    count: 1,
+                    count: sample_count,
                     mask: !0,
                     alpha_to_coverage_enabled: false,
                 },
                 fragment: Some(wgpu::FragmentState {
-                    module: &fragment_shader,
-                    entry_point: "main",
+                    module: fragment.0,
+                    entry_point: fragment.1,
                     targets: &[wgpu::ColorTargetState {
-                        format: graphics.surface_config.format,
-                        blend: Some(wgpu::BlendState::REPLACE),
+                        format: HDR_FORMAT,
+                        blend: Some(style.blend),
                         write_mask: wgpu::ColorWrites::ALL,
                     }],
                 }),
@@ -64,46 +167,141 @@ impl Pipeline {
         Self { pipeline }
     }
 
-    pub fn main_pipeline(graphics: &Graphics, uniform: &RenderPassData) -> Pipeline {
-        let vertex_buffer_layouts = vec![
-            Vertex::init_buffer_layout(),
-            InstanceRaw::init_buffer_layout(),
-        ];
-        let layout = &graphics
-            .device
-            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                label: Some("main pipeline layout"),
-                bind_group_layouts: &uniform.bind_group_layouts(),
-                push_constant_ranges: &[],
-            });
+    pub fn main_pipeline(
+        graphics: &Graphics,
+        uniform: &RenderPassData,
+        shader: &wgpu::ShaderModule,
+        sample_count: u32,
+    ) -> Pipeline {
+        Pipeline::block_pipeline(graphics, uniform, shader, "main", Type::Main, sample_count)
+    }
+
+    /// Blends translucent faces over the opaque scene. Depth is tested but
+    /// not written, so faces have to be drawn back to front.
+    pub fn translucent_pipeline(
+        graphics: &Graphics,
+        uniform: &RenderPassData,
+        shader: &wgpu::ShaderModule,
+        sample_count: u32,
+    ) -> Pipeline {
+        Pipeline::block_pipeline(
+            graphics,
+            uniform,
+            shader,
+            "translucent",
+            Type::Translucent,
+            sample_count,
+        )
+    }
+
+    /// Pipeline for one of the debug views in `Type::DEBUG_VIEWS`. Chunk
+    /// borders are drawn as lines from a buffer of positions, the other
+    /// views draw the chunk meshes with flat colors.
+    pub fn debug_pipeline(
+        graphics: &Graphics,
+        uniform: &RenderPassData,
+        shader: &wgpu::ShaderModule,
+        view: Type,
+        sample_count: u32,
+    ) -> Pipeline {
+        let (vertex_entry, fragment_entry, vertex_layout) = match view {
+            Type::Wireframe => ("vs_main", "fs_wireframe", Pipeline::block_layouts()),
+            Type::Normals => ("vs_main", "fs_normals", Pipeline::block_layouts()),
+            Type::ChunkTint => ("vs_main", "fs_chunk_tint", Pipeline::block_layouts()),
+            Type::Overdraw => ("vs_main", "fs_overdraw", Pipeline::block_layouts()),
+            Type::ChunkBorders => (
+                "vs_line",
+                "fs_line",
+                vec![ChunkBorders::init_buffer_layout()],
+            ),
+            Type::Main | Type::Translucent => panic!("{:?} is not a debug view", view),
+        };
+        let label = format!("{:?} debug", view);
+        let layout = Pipeline::block_pipeline_layout(&graphics, &uniform, &label);
         Pipeline::new(
             &graphics,
-            "main",
-            "vertex.vert.spv",
-            "fragment.frag.spv",
-            vertex_buffer_layouts,
-            Some(layout),
+            &label,
+            (shader, vertex_entry),
+            (shader, fragment_entry),
+            vertex_layout,
+            Some(&layout),
             Some(Texture::DEPTH_FORMAT),
+            Style::new(&view),
+            sample_count,
         )
     }
 
-    pub fn load_shader(graphics: &Graphics, path: &str) -> wgpu::ShaderModule {
-        let shader_dir =
-            std::path::Path::new(std::env::current_dir().unwrap().as_os_str()).join("src/shaders");
-        unsafe {
-            graphics
-                .device
-                .create_shader_module_spirv(&wgpu::ShaderModuleDescriptorSpirV {
-                    label: Some(&format!("{} shader", path)),
-                    source: wgpu::util::make_spirv_raw(
-                        &std::fs::read(shader_dir.join(path)).unwrap(),
-                    ),
-                })
-        }
+    fn block_pipeline(
+        graphics: &Graphics,
+        uniform: &RenderPassData,
+        shader: &wgpu::ShaderModule,
+        label: &str,
+        view: Type,
+        sample_count: u32,
+    ) -> Pipeline {
+        let layout = Pipeline::block_pipeline_layout(&graphics, &uniform, label);
+        Pipeline::new(
+            &graphics,
+            label,
+            (shader, "vs_main"),
+            (shader, "fs_main"),
+            Pipeline::block_layouts(),
+            Some(&layout),
+            Some(Texture::DEPTH_FORMAT),
+            Style::new(&view),
+            sample_count,
+        )
+    }
+
+    fn block_layouts<'a>() -> Vec<wgpu::VertexBufferLayout<'a>> {
+        vec![
+            Vertex::init_buffer_layout(),
+            InstanceRaw::init_buffer_layout(),
+        ]
+    }
+
+    fn block_pipeline_layout(
+        graphics: &Graphics,
+        uniform: &RenderPassData,
+        label: &str,
+    ) -> wgpu::PipelineLayout {
+        graphics
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(&format!("{} pipeline layout", label)),
+                bind_group_layouts: &uniform.bind_group_layouts(),
+                push_constant_ranges: &[],
+            })
     }
 }
 
-#[derive(Eq, PartialEq, Hash)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
 pub enum Type {
     Main,
+    Translucent,
+    /// Outlines of every face.
+    Wireframe,
+    /// The normal scene with line boxes around the loaded chunks.
+    ChunkBorders,
+    /// Faces colored by the direction they face.
+    Normals,
+    /// Faces colored by the chunk they belong to.
+    ChunkTint,
+    /// Heatmap of how often each pixel is drawn.
+    Overdraw,
+}
+
+impl Type {
+    pub const DEBUG_VIEWS: [Type; 5] = [
+        Type::Wireframe,
+        Type::ChunkBorders,
+        Type::Normals,
+        Type::ChunkTint,
+        Type::Overdraw,
+    ];
+
+    /// Whether the chunk meshes are drawn with the normal block pipelines.
+    pub fn draws_scene(&self) -> bool {
+        matches!(self, Type::Main | Type::ChunkBorders)
+    }
 }
@@ -0,0 +1,453 @@
+use super::graphics::Graphics;
+use std::collections::HashMap;
+use wgpu::util::DeviceExt;
+
+/// Format the scene is rendered in before post processing.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+// Tonemapped images between two passes, stored gamma encoded.
+const LDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const GAMMA: f32 = 2.2;
+
+const EXPOSURE: f32 = 1.0;
+const BLOOM_THRESHOLD: f32 = 1.0;
+const BLOOM_STRENGTH: f32 = 0.6;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum PostEffect {
+    /// Blurs the brightest parts of the image and adds them back in the
+    /// tonemap pass.
+    Bloom,
+    /// Maps the HDR scene to displayable colors and applies gamma.
+    Tonemap,
+    /// Fast approximate anti aliasing, runs on the tonemapped image.
+    Fxaa,
+}
+
+pub const DEFAULT_CHAIN: [PostEffect; 3] =
+    [PostEffect::Bloom, PostEffect::Tonemap, PostEffect::Fxaa];
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct PostParams {
+    texel_size: [f32; 2],
+    exposure: f32,
+    bloom_strength: f32,
+    threshold: f32,
+    output_gamma: f32,
+    _padding: [f32; 2],
+}
+
+struct RenderTarget {
+    _texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    size: (u32, u32),
+}
+
+impl RenderTarget {
+    fn new(
+        graphics: &Graphics,
+        label: &str,
+        format: wgpu::TextureFormat,
+        size: (u32, u32),
+    ) -> Self {
+        let texture = graphics.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size.0.max(1),
+                height: size.1.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        Self {
+            _texture: texture,
+            view,
+            size,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum Source {
+    Scene,
+    Target(usize),
+    // Used when there is no bloom texture.
+    Black,
+}
+
+// Description of a pass before its resources are created.
+struct Step {
+    label: &'static str,
+    entry: &'static str,
+    source: Source,
+    bloom: Source,
+    output: Option<usize>,
+    format: wgpu::TextureFormat,
+    output_gamma: f32,
+}
+
+impl Step {
+    fn new(
+        label: &'static str,
+        entry: &'static str,
+        source: Source,
+        output: Option<usize>,
+    ) -> Self {
+        Self {
+            label,
+            entry,
+            source,
+            bloom: Source::Black,
+            output,
+            format: HDR_FORMAT,
+            output_gamma: 1.,
+        }
+    }
+}
+
+// A fullscreen pass, writing into the frame when `output` is `None`.
+struct PostPass {
+    label: &'static str,
+    pipeline: (&'static str, wgpu::TextureFormat),
+    bind_group: wgpu::BindGroup,
+    output: Option<usize>,
+}
+
+/// Offscreen HDR scene target and the chain of fullscreen passes which
+/// turn it into the final frame.
+pub struct PostProcessing {
+    chain: Vec<PostEffect>,
+    scene: RenderTarget,
+    black: RenderTarget,
+    targets: Vec<RenderTarget>,
+    passes: Vec<PostPass>,
+    pipelines: HashMap<(&'static str, wgpu::TextureFormat), wgpu::RenderPipeline>,
+    shader: wgpu::ShaderModule,
+    layout: wgpu::PipelineLayout,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+}
+
+impl PostProcessing {
+    /// The chain has to end with a pass that writes displayable colors,
+    /// either `Tonemap` or `Fxaa`.
+    pub fn new(graphics: &Graphics, chain: &[PostEffect]) -> Self {
+        assert!(
+            matches!(
+                chain.last(),
+                Some(PostEffect::Tonemap) | Some(PostEffect::Fxaa)
+            ),
+            "post processing chain has to end with tonemapping or FXAA"
+        );
+        let shader = graphics
+            .device
+            .create_shader_module(&wgpu::include_wgsl!("../shaders/post.wgsl"));
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout =
+            graphics
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("post processing bind group layout"),
+                    entries: &[
+                        texture_entry(0),
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler {
+                                filtering: true,
+                                comparison: false,
+                            },
+                            count: None,
+                        },
+                        texture_entry(2),
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 3,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+        let layout = graphics
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("post processing pipeline layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let sampler = graphics.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post processing sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+        let size = (graphics.size.width, graphics.size.height);
+        let mut post = Self {
+            chain: chain.to_vec(),
+            scene: RenderTarget::new(&graphics, "hdr scene", HDR_FORMAT, size),
+            black: RenderTarget::new(&graphics, "post black", HDR_FORMAT, (1, 1)),
+            targets: Vec::new(),
+            passes: Vec::new(),
+            pipelines: HashMap::new(),
+            shader,
+            layout,
+            bind_group_layout,
+            sampler,
+        };
+        post.build(&graphics);
+        post
+    }
+
+    /// Color target the scene is rendered into.
+    pub fn scene_view(&self) -> &wgpu::TextureView {
+        &self.scene.view
+    }
+
+    pub fn resize(&mut self, graphics: &Graphics) {
+        let size = (graphics.size.width, graphics.size.height);
+        self.scene = RenderTarget::new(&graphics, "hdr scene", HDR_FORMAT, size);
+        self.build(&graphics);
+    }
+
+    // Creates the intermediate targets and passes for the chain.
+    fn build(&mut self, graphics: &Graphics) {
+        let size = self.scene.size;
+        let frame_format = graphics.surface_config.format;
+        // Srgb frames encode gamma themselves and expect linear colors.
+        let frame_gamma = if frame_format.describe().srgb {
+            1.
+        } else {
+            1. / GAMMA
+        };
+
+        self.targets.clear();
+        let mut steps = Vec::new();
+        let mut current = Source::Scene;
+        let mut bloom = Source::Black;
+        for (i, effect) in self.chain.iter().enumerate() {
+            let last = i == self.chain.len() - 1;
+            match effect {
+                PostEffect::Bloom => {
+                    let half = ((size.0 / 2).max(1), (size.1 / 2).max(1));
+                    let a = self.targets.len();
+                    self.targets
+                        .push(RenderTarget::new(&graphics, "bloom a", HDR_FORMAT, half));
+                    self.targets
+                        .push(RenderTarget::new(&graphics, "bloom b", HDR_FORMAT, half));
+                    steps.push(Step::new(
+                        "bloom bright pass",
+                        "fs_bright",
+                        current,
+                        Some(a),
+                    ));
+                    steps.push(Step::new(
+                        "bloom blur pass",
+                        "fs_blur_horizontal",
+                        Source::Target(a),
+                        Some(a + 1),
+                    ));
+                    steps.push(Step::new(
+                        "bloom blur pass",
+                        "fs_blur_vertical",
+                        Source::Target(a + 1),
+                        Some(a),
+                    ));
+                    bloom = Source::Target(a);
+                }
+                PostEffect::Tonemap | PostEffect::Fxaa => {
+                    let (label, entry, input_gamma) = if *effect == PostEffect::Tonemap {
+                        ("tonemap pass", "fs_tonemap", 1.)
+                    } else {
+                        // Runs on the gamma encoded output of the tonemapping.
+                        ("fxaa pass", "fs_fxaa", GAMMA)
+                    };
+                    let mut step = Step::new(label, entry, current, None);
+                    if last {
+                        step.format = frame_format;
+                        step.output_gamma = input_gamma * frame_gamma;
+                    } else {
+                        self.targets
+                            .push(RenderTarget::new(&graphics, label, LDR_FORMAT, size));
+                        let output = self.targets.len() - 1;
+                        step.output = Some(output);
+                        step.format = LDR_FORMAT;
+                        step.output_gamma = input_gamma / GAMMA;
+                        current = Source::Target(output);
+                    }
+                    if *effect == PostEffect::Tonemap {
+                        step.bloom = bloom;
+                    }
+                    steps.push(step);
+                }
+            }
+        }
+
+        let mut passes = Vec::new();
+        for step in steps {
+            let source_size = match step.source {
+                Source::Target(t) => self.targets[t].size,
+                _ => size,
+            };
+            let params = PostParams {
+                texel_size: [1. / source_size.0 as f32, 1. / source_size.1 as f32],
+                exposure: EXPOSURE,
+                bloom_strength: BLOOM_STRENGTH,
+                threshold: BLOOM_THRESHOLD,
+                output_gamma: step.output_gamma,
+                _padding: [0.; 2],
+            };
+            let bind_group = self.bind_group(&graphics, step.source, step.bloom, params);
+            self.create_pipeline(&graphics, step.entry, step.format);
+            passes.push(PostPass {
+                label: step.label,
+                pipeline: (step.entry, step.format),
+                bind_group,
+                output: step.output,
+            });
+        }
+        self.passes = passes;
+    }
+
+    fn view(&self, source: Source) -> &wgpu::TextureView {
+        match source {
+            Source::Scene => &self.scene.view,
+            Source::Target(t) => &self.targets[t].view,
+            Source::Black => &self.black.view,
+        }
+    }
+
+    fn bind_group(
+        &self,
+        graphics: &Graphics,
+        source: Source,
+        bloom: Source,
+        params: PostParams,
+    ) -> wgpu::BindGroup {
+        let buffer = graphics
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("post processing params buffer"),
+                contents: bytemuck::cast_slice(&[params]),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+        graphics
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("post processing bind group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(self.view(source)),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::Sampler(&self.sampler),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(self.view(bloom)),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: buffer.as_entire_binding(),
+                    },
+                ],
+            })
+    }
+
+    fn create_pipeline(
+        &mut self,
+        graphics: &Graphics,
+        entry: &'static str,
+        format: wgpu::TextureFormat,
+    ) {
+        if self.pipelines.contains_key(&(entry, format)) {
+            return;
+        }
+        let pipeline = graphics
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(entry),
+                layout: Some(&self.layout),
+                vertex: wgpu::VertexState {
+                    module: &self.shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &self.shader,
+                    entry_point: entry,
+                    targets: &[format.into()],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+            });
+        self.pipelines.insert((entry, format), pipeline);
+    }
+
+    /// Runs every pass of the chain, the last one writes into `frame`.
+    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, frame: &wgpu::TextureView) {
+        for pass in self.passes.iter() {
+            self.run_pass(encoder, pass, frame);
+        }
+    }
+
+    /// Runs only the last pass into `frame`, reading what `render` left in
+    /// the intermediate targets.
+    pub fn render_last(&self, encoder: &mut wgpu::CommandEncoder, frame: &wgpu::TextureView) {
+        if let Some(pass) = self.passes.last() {
+            self.run_pass(encoder, pass, frame);
+        }
+    }
+
+    fn run_pass(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        pass: &PostPass,
+        frame: &wgpu::TextureView,
+    ) {
+        let view = match pass.output {
+            Some(o) => &self.targets[o].view,
+            None => frame,
+        };
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some(pass.label),
+            color_attachments: &[wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(&self.pipelines[&pass.pipeline]);
+        render_pass.set_bind_group(0, &pass.bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,121 @@
+use super::graphics::Graphics;
+use anyhow::{anyhow, Result};
+use futures::task::{noop_waker_ref, Context, Poll};
+use futures::FutureExt;
+use std::future::Future;
+use std::num::NonZeroU32;
+use std::pin::Pin;
+
+type Mapping = Pin<Box<dyn Future<Output = Result<(), wgpu::BufferAsyncError>> + Send>>;
+
+/// Buffer a color texture is copied into to read it on the CPU.
+pub struct Readback {
+    buffer: wgpu::Buffer,
+    width: u32,
+    height: u32,
+    // Rows of a texture copy are aligned to COPY_BYTES_PER_ROW_ALIGNMENT.
+    padded_row: u32,
+    // Stored as BGRA, swapped to RGBA when read.
+    bgra: bool,
+}
+
+impl Readback {
+    pub fn new(graphics: &Graphics, width: u32, height: u32, format: wgpu::TextureFormat) -> Self {
+        let align = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+        let padded_row = (width * 4 + align - 1) / align * align;
+        let buffer = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback buffer"),
+            size: (padded_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+        Self {
+            buffer,
+            width,
+            height,
+            padded_row,
+            bgra: matches!(
+                format,
+                wgpu::TextureFormat::Bgra8Unorm | wgpu::TextureFormat::Bgra8UnormSrgb
+            ),
+        }
+    }
+
+    pub fn copy_from(&self, encoder: &mut wgpu::CommandEncoder, texture: &wgpu::Texture) {
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &self.buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(self.padded_row),
+                    rows_per_image: None,
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+    }
+
+    /// Starts mapping the buffer, has to be called after the copy was
+    /// submitted.
+    pub fn map(self) -> PendingReadback {
+        let mapping = Box::pin(self.buffer.slice(..).map_async(wgpu::MapMode::Read));
+        PendingReadback {
+            readback: self,
+            mapping,
+        }
+    }
+
+    fn to_image(&self) -> Result<image::RgbaImage> {
+        let data = self.buffer.slice(..).get_mapped_range();
+        let mut pixels = Vec::with_capacity((self.width * self.height * 4) as usize);
+        for row in data.chunks(self.padded_row as usize) {
+            pixels.extend_from_slice(&row[..(self.width * 4) as usize]);
+        }
+        drop(data);
+        self.buffer.unmap();
+        if self.bgra {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .ok_or_else(|| anyhow!("Readback has the wrong size"))
+    }
+}
+
+/// Readback whose buffer is being mapped.
+pub struct PendingReadback {
+    readback: Readback,
+    mapping: Mapping,
+}
+
+impl PendingReadback {
+    /// Returns the image once the mapping finished, without blocking. The
+    /// device has to be polled for the mapping to make progress.
+    pub fn try_finish(&mut self) -> Option<Result<image::RgbaImage>> {
+        let mut context = Context::from_waker(noop_waker_ref());
+        match self.mapping.poll_unpin(&mut context) {
+            Poll::Ready(Ok(())) => Some(self.readback.to_image()),
+            Poll::Ready(Err(e)) => Some(Err(anyhow!("Couldn't map the readback: {:?}", e))),
+            Poll::Pending => None,
+        }
+    }
+
+    /// Blocks until the image is available.
+    pub fn wait(mut self, graphics: &Graphics) -> Result<image::RgbaImage> {
+        graphics.device.poll(wgpu::Maintain::Wait);
+        futures::executor::block_on(&mut self.mapping)
+            .map_err(|e| anyhow!("Couldn't map the readback: {:?}", e))?;
+        self.readback.to_image()
+    }
+}
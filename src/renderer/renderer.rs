@@ -1,5 +1,9 @@
+use super::chunk_borders::ChunkBorders;
 use super::graphics::Graphics;
-use super::pipeline::{Pipeline, Type};
+use super::pipeline::{Pipeline, ShaderSources, Type};
+use super::post::{PostProcessing, DEFAULT_CHAIN, HDR_FORMAT};
+use super::readback::{PendingReadback, Readback};
+use super::sky::Sky;
 use crate::camera::Camera;
 use crate::chunk_builder::ChunkGenerator;
 use crate::debug_info::DebugInfo;
@@ -7,64 +11,353 @@ use crate::frustum_culling::Frustum;
 use crate::texture::Texture;
 use crate::uniform::RenderPassData;
 use crate::world::World;
+use futures::executor::block_on;
 use std::collections::HashMap;
 
+/// What a screenshot taken with the frame contains.
+#[derive(Copy, Clone, PartialEq)]
+pub enum Capture {
+    Frame,
+    WithOverlay,
+}
+
+// MSAA sample counts to try, 1 disables multisampling and always works.
+const SAMPLE_COUNTS: [u32; 4] = [1, 2, 4, 8];
+const DEFAULT_SAMPLE_COUNT: u32 = 4;
+
 pub struct Renderer {
     pub pipelines: HashMap<Type, Pipeline>,
+    pub sky: Sky,
+    pub post: PostProcessing,
+    /// `Type::Main` or one of the debug views.
+    pub view: Type,
+    shaders: ShaderSources,
+    // Debug views the device can draw, in the order they are cycled.
+    debug_views: Vec<Type>,
+    // Sample counts the adapter supports for the HDR and depth targets.
+    sample_counts: Vec<u32>,
+    sample_count: u32,
     depth_texture_view: wgpu::TextureView,
+    // Multisampled color target, resolved into the HDR scene target.
+    msaa_view: Option<wgpu::TextureView>,
+    // Kept while the chunk borders view is on.
+    chunk_borders: Option<ChunkBorders>,
 }
 
 impl Renderer {
     pub fn new(graphics: &Graphics, uniforms: &RenderPassData) -> Self {
-        let mut pipelines = HashMap::new();
-        pipelines.insert(Type::Main, Pipeline::main_pipeline(&graphics, uniforms));
-        let depth_texture_view = Texture::create_depth_texture_view(&graphics);
+        let sample_counts = Renderer::supported_sample_counts(&graphics);
+        let sample_count = if sample_counts.contains(&DEFAULT_SAMPLE_COUNT) {
+            DEFAULT_SAMPLE_COUNT
+        } else {
+            1
+        };
+        let shaders = ShaderSources::new();
+        let debug_views = Renderer::supported_debug_views(&graphics);
         Self {
-            pipelines,
-            depth_texture_view,
+            pipelines: Renderer::create_pipelines(
+                &graphics,
+                &uniforms,
+                &shaders,
+                &debug_views,
+                sample_count,
+            ),
+            sky: Sky::new(&graphics, sample_count),
+            post: PostProcessing::new(&graphics, &DEFAULT_CHAIN),
+            view: Type::Main,
+            shaders,
+            debug_views,
+            sample_counts,
+            sample_count,
+            depth_texture_view: Texture::create_depth_texture_view(&graphics, sample_count),
+            msaa_view: Renderer::create_msaa_view(&graphics, sample_count),
+            chunk_borders: None,
         }
     }
 
+    pub fn sample_count(&self) -> u32 {
+        self.sample_count
+    }
+
+    /// MSAA sample counts supported by the adapter, starting with 1.
+    pub fn sample_counts(&self) -> &[u32] {
+        &self.sample_counts
+    }
+
+    // wgpu has no query for the sample counts of a format, so small
+    // multisampled targets are created and every count that fails
+    // validation is left out.
+    fn supported_sample_counts(graphics: &Graphics) -> Vec<u32> {
+        SAMPLE_COUNTS
+            .iter()
+            .copied()
+            .filter(|&sample_count| {
+                if sample_count == 1 {
+                    return true;
+                }
+                graphics
+                    .device
+                    .push_error_scope(wgpu::ErrorFilter::Validation);
+                for format in [HDR_FORMAT, Texture::DEPTH_FORMAT] {
+                    graphics.device.create_texture(&wgpu::TextureDescriptor {
+                        label: Some("MSAA probe texture"),
+                        size: wgpu::Extent3d {
+                            width: 1,
+                            height: 1,
+                            depth_or_array_layers: 1,
+                        },
+                        mip_level_count: 1,
+                        sample_count,
+                        dimension: wgpu::TextureDimension::D2,
+                        format,
+                        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+                    });
+                }
+                block_on(graphics.device.pop_error_scope()).is_none()
+            })
+            .collect()
+    }
+
+    /// Recreates everything drawn in the main pass for a new MSAA sample
+    /// count, which has to be one of `sample_counts`.
+    pub fn set_sample_count(
+        &mut self,
+        graphics: &Graphics,
+        uniforms: &RenderPassData,
+        sample_count: u32,
+    ) {
+        assert!(self.sample_counts.contains(&sample_count));
+        self.sample_count = sample_count;
+        self.pipelines = Renderer::create_pipelines(
+            &graphics,
+            &uniforms,
+            &self.shaders,
+            &self.debug_views,
+            sample_count,
+        );
+        self.sky = Sky::new(&graphics, sample_count);
+        self.resize(&graphics);
+    }
+
+    /// Rebuilds the pipelines after the shader file `file_name` changed.
+    /// If the new source fails to compile the old pipelines are kept and
+    /// the error is returned. Only `block.wgsl` and `debug.wgsl` can be
+    /// reloaded, changes to the other shaders return an error as well.
+    pub fn reload_shader(
+        &mut self,
+        graphics: &Graphics,
+        uniforms: &RenderPassData,
+        file_name: &str,
+        source: String,
+    ) -> Result<(), String> {
+        let mut shaders = self.shaders.clone();
+        if !shaders.set(file_name, source) {
+            return Err(format!(
+                "{}: not hot reloadable, restart to apply the change",
+                file_name
+            ));
+        }
+        graphics
+            .device
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipelines = Renderer::create_pipelines(
+            &graphics,
+            &uniforms,
+            &shaders,
+            &self.debug_views,
+            self.sample_count,
+        );
+        match block_on(graphics.device.pop_error_scope()) {
+            Some(e) => Err(format!("{}: {}", file_name, e)),
+            None => {
+                self.pipelines = pipelines;
+                self.shaders = shaders;
+                Ok(())
+            }
+        }
+    }
+
+    /// Switches to the next debug view, after the last one back to the
+    /// normal view.
+    pub fn cycle_view(&mut self) {
+        let next = self
+            .debug_views
+            .iter()
+            .position(|v| *v == self.view)
+            .map_or(0, |i| i + 1);
+        self.view = self.debug_views.get(next).copied().unwrap_or(Type::Main);
+    }
+
+    // The wireframe view needs line polygons, which not every device has.
+    fn supported_debug_views(graphics: &Graphics) -> Vec<Type> {
+        let lines = graphics
+            .device
+            .features()
+            .contains(wgpu::Features::NON_FILL_POLYGON_MODE);
+        Type::DEBUG_VIEWS
+            .iter()
+            .copied()
+            .filter(|&view| lines || view != Type::Wireframe)
+            .collect()
+    }
+
+    fn create_pipelines(
+        graphics: &Graphics,
+        uniforms: &RenderPassData,
+        shaders: &ShaderSources,
+        debug_views: &[Type],
+        sample_count: u32,
+    ) -> HashMap<Type, Pipeline> {
+        let (block_shader, debug_shader) = shaders.create_modules(&graphics);
+        let mut pipelines = HashMap::new();
+        pipelines.insert(
+            Type::Main,
+            Pipeline::main_pipeline(&graphics, uniforms, &block_shader, sample_count),
+        );
+        pipelines.insert(
+            Type::Translucent,
+            Pipeline::translucent_pipeline(&graphics, uniforms, &block_shader, sample_count),
+        );
+        for view in debug_views {
+            pipelines.insert(
+                *view,
+                Pipeline::debug_pipeline(&graphics, uniforms, &debug_shader, *view, sample_count),
+            );
+        }
+        pipelines
+    }
+
+    fn create_capture_texture(graphics: &Graphics) -> wgpu::Texture {
+        graphics.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("screenshot texture"),
+            size: wgpu::Extent3d {
+                width: graphics.surface_config.width,
+                height: graphics.surface_config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: graphics.surface_config.format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        })
+    }
+
+    fn create_msaa_view(graphics: &Graphics, sample_count: u32) -> Option<wgpu::TextureView> {
+        if sample_count == 1 {
+            return None;
+        }
+        let texture = graphics.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("MSAA color texture"),
+            size: wgpu::Extent3d {
+                width: graphics.surface_config.width,
+                height: graphics.surface_config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        });
+        Some(texture.create_view(&wgpu::TextureViewDescriptor::default()))
+    }
+
     pub fn render(
-        &self,
+        &mut self,
         graphics: &Graphics,
         world: &World,
         uniform: &RenderPassData,
-        debug_info: &mut DebugInfo,
+        debug_info: Option<&mut DebugInfo>,
         camera: &Camera,
         frustum: &Frustum,
         chunk_gen: &ChunkGenerator,
-    ) -> Result<(), wgpu::SurfaceError> {
+        capture: Option<Capture>,
+    ) -> Result<Option<PendingReadback>, wgpu::SurfaceError> {
+        self.sky.update(&graphics, &camera, &world.clock.lighting());
         let mut encoder = graphics
             .device
             .create_command_encoder(&wgpu::CommandEncoderDescriptor {
                 label: Some("Main Command Encoder"),
             });
-        let frame = graphics.surface.get_current_frame()?.output;
+        uniform.shadow.render(&mut encoder, &world, &uniform);
+        let frame = graphics.current_frame()?;
+        if self.view != Type::ChunkBorders {
+            self.chunk_borders = None;
+        } else if !matches!(&self.chunk_borders, Some(b) if b.matches(world.chunks.keys())) {
+            self.chunk_borders = Some(ChunkBorders::new(&graphics, world.chunks.keys()));
+        }
         {
-            let view = frame
-                .texture
-                .create_view(&wgpu::TextureViewDescriptor::default());
-            let render_pass_builder =
-                RenderPassBuilder::init(&view, Some(&self.depth_texture_view));
+            let render_pass_builder = match &self.msaa_view {
+                Some(msaa_view) => RenderPassBuilder::init(
+                    msaa_view,
+                    Some(self.post.scene_view()),
+                    Some(&self.depth_texture_view),
+                ),
+                None => RenderPassBuilder::init(
+                    self.post.scene_view(),
+                    None,
+                    Some(&self.depth_texture_view),
+                ),
+            };
             let desc = render_pass_builder.build();
             let mut pass = encoder.begin_render_pass(&desc);
+            // Overdraw is counted on a black background.
+            if self.view != Type::Overdraw {
+                self.sky.render(&mut pass);
+            }
             world.render(&mut pass, &self, &uniform, &frustum);
+            if let Some(chunk_borders) = &self.chunk_borders {
+                pass.set_pipeline(&self.pipelines[&Type::ChunkBorders].pipeline);
+                chunk_borders.render(&mut pass, &uniform);
+            }
         }
-        let view = frame
-            .texture
-            .create_view(&wgpu::TextureViewDescriptor::default());
-        debug_info
-            .draw(&graphics, &mut encoder, &view, &camera, &world, &chunk_gen)
-            .unwrap();
-        debug_info.finish();
+        self.post.render(&mut encoder, &frame.view);
+        // The surface can't be copied from, the last post processing pass
+        // runs a second time into a texture which can.
+        let capture = capture.map(|capture| {
+            let texture = Renderer::create_capture_texture(&graphics);
+            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+            self.post.render_last(&mut encoder, &view);
+            (capture, texture, view)
+        });
+        if let Some(debug_info) = debug_info {
+            debug_info
+                .draw(
+                    &graphics,
+                    &mut encoder,
+                    &frame.view,
+                    &camera,
+                    &world,
+                    &chunk_gen,
+                )
+                .unwrap();
+            if let Some((Capture::WithOverlay, _, view)) = &capture {
+                debug_info
+                    .draw(&graphics, &mut encoder, view, &camera, &world, &chunk_gen)
+                    .unwrap();
+            }
+            debug_info.finish();
+        }
+        let readback = capture.as_ref().map(|(_, texture, _)| {
+            let readback = Readback::new(
+                &graphics,
+                graphics.surface_config.width,
+                graphics.surface_config.height,
+                graphics.surface_config.format,
+            );
+            readback.copy_from(&mut encoder, texture);
+            readback
+        });
         graphics.queue.submit(Some(encoder.finish()));
 
-        Ok(())
+        Ok(readback.map(Readback::map))
     }
 
     pub fn resize(&mut self, graphics: &Graphics) {
-        self.depth_texture_view = Texture::create_depth_texture_view(&graphics);
+        self.depth_texture_view = Texture::create_depth_texture_view(&graphics, self.sample_count);
+        self.msaa_view = Renderer::create_msaa_view(&graphics, self.sample_count);
+        self.post.resize(&graphics);
     }
 }
 
@@ -84,10 +377,14 @@ struct RenderPassBuilder<'a> {
 }
 
 impl<'a> RenderPassBuilder<'a> {
-    fn init(frame: &'a wgpu::TextureView, depth_view: Option<&'a wgpu::TextureView>) -> Self {
+    fn init(
+        frame: &'a wgpu::TextureView,
+        resolve_target: Option<&'a wgpu::TextureView>,
+        depth_view: Option<&'a wgpu::TextureView>,
+    ) -> Self {
         let color_attachments = vec![wgpu::RenderPassColorAttachment {
             view: frame,
-            resolve_target: None,
+            resolve_target,
             ops: wgpu::Operations {
                 load: wgpu::LoadOp::Clear(wgpu::Color {
                     r: 0.1,
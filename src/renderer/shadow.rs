@@ -0,0 +1,379 @@
+use super::graphics::Graphics;
+use super::instance::InstanceRaw;
+use super::vertex::Vertex;
+use crate::camera::{Camera, OPENGL_TO_WGPU_MATRIX};
+use crate::clock::Lighting;
+use crate::frustum_culling::Frustum;
+use crate::texture::Texture;
+use crate::uniform::RenderPassData;
+use crate::world::World;
+use nalgebra::{Matrix4, Point3, Vector3, Vector4};
+use std::num::NonZeroU32;
+use wgpu::util::DeviceExt;
+
+pub const CASCADE_COUNT: usize = 3;
+pub const SHADOW_MAP_SIZE: u32 = 2048;
+// Far distance of each cascade, nothing casts shadows past the last one.
+const CASCADE_SPLITS: [f32; CASCADE_COUNT] = [16., 48., 144.];
+// Casters this far behind a cascade towards the sun are still drawn.
+const CASTER_MARGIN: f32 = 64.;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct ShadowData {
+    light_view_proj: [[[f32; 4]; 4]; CASCADE_COUNT],
+    splits: [f32; 4],
+}
+
+/// Cascaded shadow map of the sunlight. Every cascade covers a slice of
+/// the view frustum and is rendered into one layer of a depth texture
+/// array, which the main pass samples through bind group 2.
+pub struct ShadowMap {
+    pipeline: wgpu::RenderPipeline,
+    layer_views: Vec<wgpu::TextureView>,
+    cascade_buffers: Vec<wgpu::Buffer>,
+    cascade_bind_groups: Vec<wgpu::BindGroup>,
+    // Light space bounds of every cascade, used to cull the casters.
+    cascade_frustums: Vec<Frustum>,
+    // Whether the sun is up, nothing is drawn into the cascades otherwise.
+    sunlit: bool,
+    buffer: wgpu::Buffer,
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl ShadowMap {
+    pub fn new(graphics: &Graphics) -> Self {
+        let texture = graphics.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("shadow map"),
+            size: wgpu::Extent3d {
+                width: SHADOW_MAP_SIZE,
+                height: SHADOW_MAP_SIZE,
+                depth_or_array_layers: CASCADE_COUNT as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: Texture::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        });
+        let layer_views = (0..CASCADE_COUNT as u32)
+            .map(|layer| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some(&format!("shadow cascade {}", layer)),
+                    format: None,
+                    dimension: Some(wgpu::TextureViewDimension::D2),
+                    aspect: wgpu::TextureAspect::All,
+                    base_mip_level: 0,
+                    mip_level_count: None,
+                    base_array_layer: layer,
+                    array_layer_count: NonZeroU32::new(1),
+                })
+            })
+            .collect::<Vec<_>>();
+        let array_view = texture.create_view(&wgpu::TextureViewDescriptor {
+            label: Some("shadow map array"),
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        let sampler = graphics.device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("shadow map sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            ..Default::default()
+        });
+
+        let buffer = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("shadow data buffer"),
+            size: std::mem::size_of::<ShadowData>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group_layout =
+            graphics
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("shadow map bind group layout"),
+                    entries: &[
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 0,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Buffer {
+                                ty: wgpu::BufferBindingType::Uniform,
+                                has_dynamic_offset: false,
+                                min_binding_size: None,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 1,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Texture {
+                                sample_type: wgpu::TextureSampleType::Depth,
+                                view_dimension: wgpu::TextureViewDimension::D2Array,
+                                multisampled: false,
+                            },
+                            count: None,
+                        },
+                        wgpu::BindGroupLayoutEntry {
+                            binding: 2,
+                            visibility: wgpu::ShaderStages::FRAGMENT,
+                            ty: wgpu::BindingType::Sampler {
+                                filtering: true,
+                                comparison: true,
+                            },
+                            count: None,
+                        },
+                    ],
+                });
+        let bind_group = graphics
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("shadow map bind group"),
+                layout: &bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: buffer.as_entire_binding(),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&array_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::Sampler(&sampler),
+                    },
+                ],
+            });
+
+        // Every cascade pass gets its own light matrix.
+        let cascade_layout =
+            graphics
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("shadow cascade bind group layout"),
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::VERTEX,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }],
+                });
+        let cascade_buffers = (0..CASCADE_COUNT)
+            .map(|i| {
+                graphics
+                    .device
+                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                        label: Some(&format!("shadow cascade {} buffer", i)),
+                        contents: bytemuck::cast_slice(&[[[0f32; 4]; 4]]),
+                        usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                    })
+            })
+            .collect::<Vec<_>>();
+        let cascade_bind_groups = cascade_buffers
+            .iter()
+            .map(|b| {
+                graphics
+                    .device
+                    .create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: Some("shadow cascade bind group"),
+                        layout: &cascade_layout,
+                        entries: &[wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: b.as_entire_binding(),
+                        }],
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        let pipeline = ShadowMap::create_pipeline(&graphics, &cascade_layout);
+        Self {
+            pipeline,
+            layer_views,
+            cascade_buffers,
+            cascade_bind_groups,
+            cascade_frustums: Vec::new(),
+            sunlit: false,
+            buffer,
+            bind_group_layout,
+            bind_group,
+        }
+    }
+
+    fn create_pipeline(
+        graphics: &Graphics,
+        cascade_layout: &wgpu::BindGroupLayout,
+    ) -> wgpu::RenderPipeline {
+        let shader = graphics
+            .device
+            .create_shader_module(&wgpu::include_wgsl!("../shaders/shadow.wgsl"));
+        let layout = graphics
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("shadow pipeline layout"),
+                bind_group_layouts: &[cascade_layout],
+                push_constant_ranges: &[],
+            });
+        graphics
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("shadow render pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "main",
+                    buffers: &[
+                        Vertex::init_buffer_layout(),
+                        InstanceRaw::init_buffer_layout(),
+                    ],
+                },
+                fragment: None,
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::TriangleList,
+                    // Both sides of a face block the sun.
+                    cull_mode: None,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: true,
+                    depth_compare: wgpu::CompareFunction::LessEqual,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState {
+                        constant: 2,
+                        slope_scale: 2.0,
+                        clamp: 0.0,
+                    },
+                }),
+                multisample: wgpu::MultisampleState::default(),
+            })
+    }
+
+    /// Fits every cascade around its slice of the view frustum.
+    pub fn update(&mut self, graphics: &Graphics, camera: &Camera, lighting: &Lighting) {
+        self.sunlit = lighting.skylight > 0.;
+        if !self.sunlit {
+            return;
+        }
+        let inv_view_proj = match camera.global_matrix.try_inverse() {
+            Some(m) => m,
+            None => return,
+        };
+        let (near, far) = camera.depth_range();
+        // Frustum corners on the near and far plane in world space.
+        let unproject = |x: f32, y: f32, z: f32| {
+            let p = inv_view_proj * Vector4::new(x, y, z, 1.);
+            Vector3::new(p.x / p.w, p.y / p.w, p.z / p.w)
+        };
+        let corners = [(-1., -1.), (1., -1.), (-1., 1.), (1., 1.)]
+            .iter()
+            .map(|(x, y)| (unproject(*x, *y, 0.), unproject(*x, *y, 1.)))
+            .collect::<Vec<_>>();
+
+        let sun = lighting.sun_direction;
+        let up = if sun.y.abs() > 0.99 {
+            Vector3::z()
+        } else {
+            Vector3::y()
+        };
+        let light_view = Matrix4::look_at_rh(&Point3::from(sun), &Point3::origin(), &up);
+
+        let mut data = ShadowData {
+            light_view_proj: [[[0.; 4]; 4]; CASCADE_COUNT],
+            splits: [0.; 4],
+        };
+        self.cascade_frustums.clear();
+        let mut start = near;
+        for (i, split) in CASCADE_SPLITS.iter().enumerate() {
+            let end = split.min(far);
+            // Bounding sphere of the frustum slice, which keeps the cascade
+            // size constant while the camera rotates.
+            let slice = corners
+                .iter()
+                .flat_map(|(n, f)| {
+                    let a = (start - near) / (far - near);
+                    let b = (end - near) / (far - near);
+                    vec![n + (f - n) * a, n + (f - n) * b]
+                })
+                .collect::<Vec<_>>();
+            let center = slice.iter().sum::<Vector3<f32>>() / slice.len() as f32;
+            let radius = slice
+                .iter()
+                .map(|p| (p - center).norm())
+                .fold(0., f32::max)
+                .ceil();
+
+            // Snap the center to whole texels so shadow edges don't shimmer
+            // while moving.
+            let texel = 2. * radius / SHADOW_MAP_SIZE as f32;
+            let c = light_view.transform_point(&Point3::from(center));
+            let (cx, cy) = ((c.x / texel).floor() * texel, (c.y / texel).floor() * texel);
+            let projection = Matrix4::new_orthographic(
+                cx - radius,
+                cx + radius,
+                cy - radius,
+                cy + radius,
+                -c.z - radius - CASTER_MARGIN,
+                -c.z + radius,
+            );
+            let matrix = OPENGL_TO_WGPU_MATRIX * projection * light_view;
+            data.light_view_proj[i] = matrix.into();
+            self.cascade_frustums.push(Frustum::from_matrix(matrix));
+            data.splits[i] = end;
+            graphics.queue.write_buffer(
+                &self.cascade_buffers[i],
+                0,
+                bytemuck::cast_slice(&[data.light_view_proj[i]]),
+            );
+            start = end;
+        }
+        graphics
+            .queue
+            .write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[data]));
+    }
+
+    /// Renders the shadow casters into every cascade. Skipped at night,
+    /// when the shaders don't sample the shadow map.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        world: &World,
+        uniform: &RenderPassData,
+    ) {
+        if !self.sunlit {
+            return;
+        }
+        let cascades = self
+            .layer_views
+            .iter()
+            .zip(self.cascade_bind_groups.iter())
+            .zip(self.cascade_frustums.iter());
+        for ((view, bind_group), frustum) in cascades {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("shadow pass"),
+                color_attachments: &[],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: true,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            world.render_shadow_casters(&mut pass, uniform, frustum);
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use super::graphics::Graphics;
+use super::post::HDR_FORMAT;
+use crate::camera::Camera;
+use crate::clock::Lighting;
+use crate::texture::Texture;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable, Debug)]
+struct SkyData {
+    inv_view_proj: [[f32; 4]; 4],
+    eye: [f32; 4],
+    sun_direction: [f32; 4],
+    horizon_color: [f32; 4],
+    zenith_color: [f32; 4],
+}
+
+/// Draws a gradient sky dome with a sun behind the world. Drawn first in
+/// the main pass without writing depth.
+pub struct Sky {
+    pipeline: wgpu::RenderPipeline,
+    bind_group: wgpu::BindGroup,
+    buffer: wgpu::Buffer,
+}
+
+impl Sky {
+    pub fn new(graphics: &Graphics, sample_count: u32) -> Self {
+        let shader = graphics
+            .device
+            .create_shader_module(&wgpu::include_wgsl!("../shaders/sky.wgsl"));
+        let bind_group_layout =
+            graphics
+                .device
+                .create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                    label: Some("sky bind group layout"),
+                    entries: &[wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    }],
+                });
+        let layout = graphics
+            .device
+            .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("sky pipeline layout"),
+                bind_group_layouts: &[&bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let pipeline = graphics
+            .device
+            .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("sky render pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: "vs_main",
+                    buffers: &[],
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: "fs_main",
+                    targets: &[HDR_FORMAT.into()],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare: wgpu::CompareFunction::Always,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+            });
+        let buffer = graphics.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("sky data buffer"),
+            size: std::mem::size_of::<SkyData>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let bind_group = graphics
+            .device
+            .create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("sky bind group"),
+                layout: &bind_group_layout,
+                entries: &[wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                }],
+            });
+        Self {
+            pipeline,
+            bind_group,
+            buffer,
+        }
+    }
+
+    pub fn update(&self, graphics: &Graphics, camera: &Camera, lighting: &Lighting) {
+        let inv_view_proj = camera
+            .global_matrix
+            .try_inverse()
+            .unwrap_or_else(nalgebra::Matrix4::identity);
+        let data = SkyData {
+            inv_view_proj: inv_view_proj.into(),
+            eye: [camera.eye.x, camera.eye.y, camera.eye.z, 1.],
+            sun_direction: [
+                lighting.sun_direction.x,
+                lighting.sun_direction.y,
+                lighting.sun_direction.z,
+                0.,
+            ],
+            horizon_color: Sky::with_alpha(lighting.horizon_color),
+            zenith_color: Sky::with_alpha(lighting.zenith_color),
+        };
+        graphics
+            .queue
+            .write_buffer(&self.buffer, 0, bytemuck::cast_slice(&[data]));
+    }
+
+    pub fn render<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    fn with_alpha(color: [f32; 3]) -> [f32; 4] {
+        [color[0], color[1], color[2], 1.]
+    }
+}
@@ -0,0 +1,132 @@
+[[block]]
+struct Globals {
+    matrix: mat4x4<f32>;
+    eye: vec4<f32>;
+    fog_color: vec4<f32>;
+    sun_direction: vec4<f32>;
+    time: f32;
+    fog_density: f32;
+    ambient: f32;
+    skylight: f32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> globals: Globals;
+
+[[group(1), binding(0)]]
+var t_sampler: sampler;
+[[group(1), binding(1)]]
+var textures: texture_2d_array<f32>;
+
+[[block]]
+struct Shadows {
+    light_view_proj: [[stride(64)]] array<mat4x4<f32>, 3>;
+    splits: vec4<f32>;
+};
+
+[[group(2), binding(0)]]
+var<uniform> shadows: Shadows;
+[[group(2), binding(1)]]
+var shadow_map: texture_depth_2d_array;
+[[group(2), binding(2)]]
+var shadow_sampler: sampler_comparison;
+
+let CASCADE_COUNT: i32 = 3;
+let SHADOW_MAP_SIZE: f32 = 2048.0;
+// Set on the texture index of animated fluid surfaces.
+let ANIMATED_BIT: u32 = 256u;
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] tex_coords: vec2<f32>;
+    [[location(1), interpolate(flat)]] texture_index: u32;
+    [[location(2)]] world_pos: vec3<f32>;
+    [[location(3), interpolate(flat)]] normal: vec3<f32>;
+};
+
+[[stage(vertex)]]
+fn vs_main(
+    [[location(0)]] pos: vec3<f32>,
+    [[location(1)]] tex_coords: vec2<f32>,
+    [[location(3)]] instance_1: vec4<f32>,
+    [[location(4)]] instance_2: vec4<f32>,
+    [[location(5)]] instance_3: vec4<f32>,
+    [[location(6)]] instance_4: vec4<f32>,
+    [[location(7)]] texture_index: u32
+) -> VertexOutput {
+    let instance = mat4x4<f32>(instance_1, instance_2, instance_3, instance_4);
+    var world_pos = instance * vec4<f32>(pos, 1.0);
+    var uv = tex_coords;
+    if ((texture_index & ANIMATED_BIT) != 0u) {
+        // Small waves which never rise above the cube and a flowing texture.
+        let t = globals.time;
+        world_pos.y = world_pos.y + (sin(t * 2.0 + world_pos.x * 0.7 + world_pos.z * 0.5) - 1.0) * 0.04;
+        uv = uv + vec2<f32>(t * 0.05, t * 0.03);
+    }
+    var out: VertexOutput;
+    out.position = globals.matrix * world_pos;
+    out.tex_coords = uv;
+    out.texture_index = texture_index & ~ANIMATED_BIT;
+    out.world_pos = world_pos.xyz;
+    // Faces are quads facing +z before the instance rotation.
+    out.normal = normalize((instance * vec4<f32>(0.0, 0.0, 1.0, 0.0)).xyz);
+    return out;
+}
+
+// Fraction of sunlight reaching the fragment, filtered over 3x3 texels.
+fn sunlight(distance: f32, world_pos: vec3<f32>, normal: vec3<f32>) -> f32 {
+    var cascade: i32 = 0;
+    loop {
+        if (cascade >= CASCADE_COUNT || distance <= shadows.splits[cascade]) {
+            break;
+        }
+        cascade = cascade + 1;
+    }
+    if (cascade == CASCADE_COUNT) {
+        return 1.0;
+    }
+    // Offsetting along the normal avoids self shadowing on sloped light.
+    let offset_pos = world_pos + normal * 0.04 * f32(cascade + 1);
+    let light_pos = shadows.light_view_proj[cascade] * vec4<f32>(offset_pos, 1.0);
+    let coords = light_pos.xyz / light_pos.w;
+    let uv = coords.xy * vec2<f32>(0.5, -0.5) + vec2<f32>(0.5, 0.5);
+    let texel = 1.0 / SHADOW_MAP_SIZE;
+    var lit: f32 = 0.0;
+    var x: i32 = -1;
+    loop {
+        if (x > 1) {
+            break;
+        }
+        var y: i32 = -1;
+        loop {
+            if (y > 1) {
+                break;
+            }
+            let offset = vec2<f32>(f32(x), f32(y)) * texel;
+            lit = lit + textureSampleCompareLevel(shadow_map, shadow_sampler, uv + offset, cascade, coords.z);
+            y = y + 1;
+        }
+        x = x + 1;
+    }
+    return lit / 9.0;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = textureSample(textures, t_sampler, in.tex_coords, i32(in.texture_index));
+    // Cutout blocks like leaves have fully transparent texels.
+    if (color.a < 0.1) {
+        discard;
+    }
+    let distance = length(in.world_pos - globals.eye.xyz);
+    // Lambert shading from the sun on top of the ambient light.
+    var lambert = max(dot(in.normal, globals.sun_direction.xyz), 0.0);
+    if (lambert > 0.0 && globals.skylight > 0.0) {
+        lambert = lambert * sunlight(distance, in.world_pos, in.normal);
+    }
+    let lit = color.rgb * min(globals.ambient + globals.skylight * lambert, 1.0);
+    // Exponential squared fog, so nearby terrain stays clear and chunks
+    // fade into the sky before they are streamed out.
+    let fog = 1.0 - exp(-pow(distance * globals.fog_density, 2.0));
+    return vec4<f32>(mix(lit, globals.fog_color.rgb, vec3<f32>(fog, fog, fog)), color.a);
+}
@@ -0,0 +1,93 @@
+struct ChunkBounds {
+    origin: vec4<f32>;
+    base_instance: u32;
+    instance_count: u32;
+    visible: u32;
+    pad: u32;
+};
+
+[[block]]
+struct Chunks {
+    data: array<ChunkBounds>;
+};
+
+struct DrawIndexedIndirect {
+    vertex_count: u32;
+    instance_count: u32;
+    base_index: u32;
+    vertex_offset: i32;
+    base_instance: u32;
+};
+
+[[block]]
+struct Draws {
+    data: array<DrawIndexedIndirect>;
+};
+
+[[block]]
+struct DrawCount {
+    count: atomic<u32>;
+};
+
+[[block]]
+struct Cull {
+    planes: array<vec4<f32>, 6>;
+    chunk_count: u32;
+    indices_len: u32;
+    chunk_size: f32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> cull: Cull;
+[[group(0), binding(1)]]
+var<storage, read> chunks: Chunks;
+[[group(0), binding(2)]]
+var<storage, read_write> draws: Draws;
+[[group(0), binding(3)]]
+var<storage, read_write> draw_count: DrawCount;
+
+// Resets the draw list before culling. Slots past the visible count are
+// drawn as empty draws when the count can't be read on the GPU.
+[[stage(compute), workgroup_size(64)]]
+fn clear([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let index = id.x;
+    if (index == 0u) {
+        atomicStore(&draw_count.count, 0u);
+    }
+    if (index >= cull.chunk_count) {
+        return;
+    }
+    draws.data[index].vertex_count = 0u;
+    draws.data[index].instance_count = 0u;
+}
+
+[[stage(compute), workgroup_size(64)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    let index = id.x;
+    if (index >= cull.chunk_count) {
+        return;
+    }
+    let chunk = chunks.data[index];
+    // Hidden by the occlusion culling on the CPU.
+    if (chunk.visible == 0u) {
+        return;
+    }
+    let lo = chunk.origin.xyz;
+    let hi = lo + vec3<f32>(cull.chunk_size, cull.chunk_size, cull.chunk_size);
+
+    // Test the box corner furthest along each plane normal.
+    for (var p: i32 = 0; p < 6; p = p + 1) {
+        let plane = cull.planes[p];
+        let corner = select(lo, hi, plane.xyz >= vec3<f32>(0.0, 0.0, 0.0));
+        if (dot(plane.xyz, corner) + plane.w < 0.0) {
+            return;
+        }
+    }
+
+    let slot = atomicAdd(&draw_count.count, 1u);
+    draws.data[slot].vertex_count = cull.indices_len;
+    draws.data[slot].instance_count = chunk.instance_count;
+    draws.data[slot].base_index = 0u;
+    draws.data[slot].vertex_offset = 0;
+    draws.data[slot].base_instance = chunk.base_instance;
+}
@@ -0,0 +1,85 @@
+[[block]]
+struct Globals {
+    matrix: mat4x4<f32>;
+    eye: vec4<f32>;
+    fog_color: vec4<f32>;
+    sun_direction: vec4<f32>;
+    time: f32;
+    fog_density: f32;
+    ambient: f32;
+    skylight: f32;
+};
+
+[[group(0), binding(0)]]
+var<uniform> globals: Globals;
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] world_pos: vec3<f32>;
+    [[location(1), interpolate(flat)]] normal: vec3<f32>;
+};
+
+// Same transform as vertex.vert, without the fluid animation.
+[[stage(vertex)]]
+fn vs_main(
+    [[location(0)]] pos: vec3<f32>,
+    [[location(3)]] instance_1: vec4<f32>,
+    [[location(4)]] instance_2: vec4<f32>,
+    [[location(5)]] instance_3: vec4<f32>,
+    [[location(6)]] instance_4: vec4<f32>
+) -> VertexOutput {
+    let instance = mat4x4<f32>(instance_1, instance_2, instance_3, instance_4);
+    let world_pos = instance * vec4<f32>(pos, 1.0);
+    var out: VertexOutput;
+    out.position = globals.matrix * world_pos;
+    out.world_pos = world_pos.xyz;
+    out.normal = normalize((instance * vec4<f32>(0.0, 0.0, 1.0, 0.0)).xyz);
+    return out;
+}
+
+// Darkens faces by direction so the shapes stay readable with flat colors.
+fn shade(color: vec3<f32>, normal: vec3<f32>) -> vec4<f32> {
+    let n = abs(normal);
+    return vec4<f32>(color * (n.y + n.x * 0.8 + n.z * 0.65), 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_wireframe(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(0.2, 1.0, 0.4, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_normals(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(in.normal * 0.5 + vec3<f32>(0.5, 0.5, 0.5), 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_chunk_tint(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // Cubes are centred on integer coordinates, step inside the face's cube.
+    let cube = floor(in.world_pos - in.normal * 0.25 + vec3<f32>(0.5, 0.5, 0.5));
+    let chunk = vec3<i32>(floor(cube / 32.0));
+    var h = (u32(chunk.x) * 73856093u) ^ (u32(chunk.y) * 19349663u) ^ (u32(chunk.z) * 83492791u);
+    h = (h ^ (h >> 13u)) * 1274126177u;
+    let color = vec3<f32>(
+        f32(h & 255u),
+        f32((h >> 8u) & 255u),
+        f32((h >> 16u) & 255u)
+    ) / 255.0;
+    return shade(color * 0.7 + vec3<f32>(0.3, 0.3, 0.3), in.normal);
+}
+
+// Added up for every fragment, bright areas are drawn many times.
+[[stage(fragment)]]
+fn fs_overdraw(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(0.12, 0.04, 0.01, 1.0);
+}
+
+[[stage(vertex)]]
+fn vs_line([[location(0)]] pos: vec3<f32>) -> [[builtin(position)]] vec4<f32> {
+    return globals.matrix * vec4<f32>(pos, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_line() -> [[location(0)]] vec4<f32> {
+    return vec4<f32>(1.0, 0.85, 0.1, 1.0);
+}
@@ -0,0 +1,126 @@
+[[block]]
+struct Params {
+    texel_size: vec2<f32>;
+    exposure: f32;
+    bloom_strength: f32;
+    threshold: f32;
+    // Exponent applied to the output, encodes or decodes gamma.
+    output_gamma: f32;
+};
+
+[[group(0), binding(0)]]
+var source: texture_2d<f32>;
+[[group(0), binding(1)]]
+var source_sampler: sampler;
+[[group(0), binding(2)]]
+var bloom: texture_2d<f32>;
+[[group(0), binding(3)]]
+var<uniform> params: Params;
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] tex_coords: vec2<f32>;
+};
+
+// One triangle covering the whole screen, like the mipmap blit.
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let tc = vec2<f32>(f32(i32(vertex_index) / 2) * 2.0, f32(i32(vertex_index) & 1) * 2.0);
+    out.position = vec4<f32>(tc.x * 2.0 - 1.0, 1.0 - tc.y * 2.0, 0.0, 1.0);
+    out.tex_coords = tc;
+    return out;
+}
+
+fn luma(color: vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+fn encode(color: vec3<f32>) -> vec4<f32> {
+    let g = vec3<f32>(params.output_gamma, params.output_gamma, params.output_gamma);
+    return vec4<f32>(pow(max(color, vec3<f32>(0.0, 0.0, 0.0)), g), 1.0);
+}
+
+// Keeps the parts of the image brighter than the threshold.
+[[stage(fragment)]]
+fn fs_bright(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let color = textureSample(source, source_sampler, in.tex_coords).rgb;
+    let brightness = max(color.r, max(color.g, color.b));
+    let factor = max(brightness - params.threshold, 0.0) / max(brightness, 0.0001);
+    return vec4<f32>(color * factor, 1.0);
+}
+
+fn blur(tex_coords: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    // 9 tap gaussian using linear filtering between texels.
+    let near = direction * params.texel_size * 1.3846153846;
+    let far = direction * params.texel_size * 3.2307692308;
+    var color = textureSample(source, source_sampler, tex_coords).rgb * 0.2270270270;
+    color = color + textureSample(source, source_sampler, tex_coords + near).rgb * 0.3162162162;
+    color = color + textureSample(source, source_sampler, tex_coords - near).rgb * 0.3162162162;
+    color = color + textureSample(source, source_sampler, tex_coords + far).rgb * 0.0702702703;
+    color = color + textureSample(source, source_sampler, tex_coords - far).rgb * 0.0702702703;
+    return vec4<f32>(color, 1.0);
+}
+
+[[stage(fragment)]]
+fn fs_blur_horizontal(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return blur(in.tex_coords, vec2<f32>(1.0, 0.0));
+}
+
+[[stage(fragment)]]
+fn fs_blur_vertical(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    return blur(in.tex_coords, vec2<f32>(0.0, 1.0));
+}
+
+// Fitted ACES filmic curve.
+fn aces(x: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((x * (a * x + b)) / (x * (c * x + d) + e), vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(1.0, 1.0, 1.0));
+}
+
+[[stage(fragment)]]
+fn fs_tonemap(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let hdr = textureSample(source, source_sampler, in.tex_coords).rgb;
+    let glow = textureSample(bloom, source_sampler, in.tex_coords).rgb;
+    let color = (hdr + glow * params.bloom_strength) * params.exposure;
+    return encode(aces(color));
+}
+
+// Fast approximate anti aliasing on the tonemapped image.
+[[stage(fragment)]]
+fn fs_fxaa(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    let uv = in.tex_coords;
+    let px = params.texel_size;
+    let rgb_m = textureSample(source, source_sampler, uv).rgb;
+    let luma_nw = luma(textureSample(source, source_sampler, uv + vec2<f32>(-1.0, -1.0) * px).rgb);
+    let luma_ne = luma(textureSample(source, source_sampler, uv + vec2<f32>(1.0, -1.0) * px).rgb);
+    let luma_sw = luma(textureSample(source, source_sampler, uv + vec2<f32>(-1.0, 1.0) * px).rgb);
+    let luma_se = luma(textureSample(source, source_sampler, uv + vec2<f32>(1.0, 1.0) * px).rgb);
+    let luma_m = luma(rgb_m);
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    var dir = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se)
+    );
+    let reduce = max((luma_nw + luma_ne + luma_sw + luma_se) * 0.03125, 0.0078125);
+    let scale = 1.0 / (min(abs(dir.x), abs(dir.y)) + reduce);
+    dir = clamp(dir * scale, vec2<f32>(-8.0, -8.0), vec2<f32>(8.0, 8.0)) * px;
+
+    let rgb_a = 0.5 * (
+        textureSample(source, source_sampler, uv + dir * (1.0 / 3.0 - 0.5)).rgb +
+        textureSample(source, source_sampler, uv + dir * (2.0 / 3.0 - 0.5)).rgb);
+    let rgb_b = rgb_a * 0.5 + 0.25 * (
+        textureSample(source, source_sampler, uv + dir * -0.5).rgb +
+        textureSample(source, source_sampler, uv + dir * 0.5).rgb);
+    let luma_b = luma(rgb_b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return encode(rgb_a);
+    }
+    return encode(rgb_b);
+}
@@ -0,0 +1,19 @@
+[[block]]
+struct Cascade {
+    light_view_proj: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> cascade: Cascade;
+
+[[stage(vertex)]]
+fn main(
+    [[location(0)]] pos: vec3<f32>,
+    [[location(3)]] instance_1: vec4<f32>,
+    [[location(4)]] instance_2: vec4<f32>,
+    [[location(5)]] instance_3: vec4<f32>,
+    [[location(6)]] instance_4: vec4<f32>
+) -> [[builtin(position)]] vec4<f32> {
+    let instance = mat4x4<f32>(instance_1, instance_2, instance_3, instance_4);
+    return cascade.light_view_proj * instance * vec4<f32>(pos, 1.0);
+}
@@ -0,0 +1,46 @@
+[[block]]
+struct Sky {
+    inv_view_proj: mat4x4<f32>;
+    eye: vec4<f32>;
+    sun_direction: vec4<f32>;
+    horizon_color: vec4<f32>;
+    zenith_color: vec4<f32>;
+};
+
+[[group(0), binding(0)]]
+var<uniform> sky: Sky;
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] ndc: vec2<f32>;
+};
+
+// One triangle covering the whole screen.
+[[stage(vertex)]]
+fn vs_main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let x = f32(i32(vertex_index) / 2) * 4.0 - 1.0;
+    let y = f32(i32(vertex_index) & 1) * 4.0 - 1.0;
+    out.position = vec4<f32>(x, y, 1.0, 1.0);
+    out.ndc = vec2<f32>(x, y);
+    return out;
+}
+
+[[stage(fragment)]]
+fn fs_main(in: VertexOutput) -> [[location(0)]] vec4<f32> {
+    // World space view ray through the far plane.
+    let far = sky.inv_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let dir = normalize(far.xyz / far.w - sky.eye.xyz);
+
+    let height = clamp(dir.y, 0.0, 1.0);
+    var color = mix(sky.horizon_color.rgb, sky.zenith_color.rgb, sqrt(height));
+
+    let sun = max(dot(dir, sky.sun_direction.xyz), 0.0);
+    let glow = pow(sun, 64.0) * 0.4;
+    // Brighter than white so the disk blooms after tonemapping.
+    let disk = clamp((sun - 0.9994) / 0.0003, 0.0, 1.0) * 8.0;
+    // Fades out once the sun has set.
+    let above = clamp(sky.sun_direction.y * 10.0 + 1.0, 0.0, 1.0);
+    color = color + vec3<f32>(1.0, 0.9, 0.7) * (glow + disk) * above;
+    return vec4<f32>(color, 1.0);
+}
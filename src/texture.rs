@@ -1,21 +1,24 @@
+use crate::assets::Assets;
 use crate::renderer::graphics::Graphics;
 use anyhow::Result;
 use image::{DynamicImage, GenericImageView};
 use std::num::NonZeroU32;
-use std::path::Path;
 
 pub struct Texture {
     texture: wgpu::Texture,
     pub view: wgpu::TextureView,
     mip_level_count: u32,
+    layers: u32,
 }
 
 impl Texture {
-    pub const TEXTURE_ARRAY_SIZE: u32 = 4;
+    pub const TEXTURE_ARRAY_SIZE: u32 = 8;
+    // Width and height of every block texture, larger images are scaled down.
+    pub const TEXTURE_SIZE: u32 = 16;
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-    pub fn create_depth_texture_view(graphics: &Graphics) -> wgpu::TextureView {
+    pub fn create_depth_texture_view(graphics: &Graphics, sample_count: u32) -> wgpu::TextureView {
         let size = {
             wgpu::Extent3d {
                 width: graphics.surface_config.width,
@@ -27,7 +30,7 @@ impl Texture {
             label: Some("Depth Texture"),
             size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: Self::DEPTH_FORMAT,
             usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
@@ -39,21 +42,20 @@ impl Texture {
         view
     }
 
-    fn from_image(
+    // Uploads the images as layers of one texture, every layer has to be
+    // TEXTURE_SIZE squared.
+    fn from_images(
         graphics: &Graphics,
         label: Option<&str>,
-        image: DynamicImage,
+        images: Vec<DynamicImage>,
     ) -> Result<Texture> {
-        let rgba = image.to_rgba8();
-        let dimensions = image.dimensions();
-
         let size = wgpu::Extent3d {
-            width: dimensions.0,
-            height: dimensions.1,
-            depth_or_array_layers: 1,
+            width: Self::TEXTURE_SIZE,
+            height: Self::TEXTURE_SIZE,
+            depth_or_array_layers: images.len() as u32,
         };
 
-        let mip_level_count = 1 + ((dimensions.0.max(dimensions.1) as f32).log2().floor() as u32);
+        let mip_level_count = 1 + ((Self::TEXTURE_SIZE as f32).log2().floor() as u32);
         let texture = graphics.device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
@@ -66,41 +68,98 @@ impl Texture {
                 | wgpu::TextureUsages::COPY_DST,
         });
 
-        graphics.queue.write_texture(
-            wgpu::ImageCopyTexture {
-                texture: &texture,
-                mip_level: 0,
-                origin: wgpu::Origin3d::ZERO,
-                aspect: wgpu::TextureAspect::All,
-            },
-            &rgba,
-            wgpu::ImageDataLayout {
-                offset: 0,
-                bytes_per_row: NonZeroU32::new(4 * dimensions.0),
-                rows_per_image: None,
-            },
-            size,
-        );
+        for (layer, image) in images.iter().enumerate() {
+            let rgba = image.to_rgba8();
+            graphics.queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d {
+                        x: 0,
+                        y: 0,
+                        z: layer as u32,
+                    },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                &rgba,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(4 * Self::TEXTURE_SIZE),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    depth_or_array_layers: 1,
+                    ..size
+                },
+            );
+        }
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            ..Default::default()
+        });
 
         Ok(Self {
             texture,
             view,
             mip_level_count,
+            layers: size.depth_or_array_layers,
         })
     }
 
-    fn from_path<P: AsRef<Path>>(graphics: &Graphics, path: P) -> Result<Texture> {
-        let buf = path.as_ref().to_path_buf();
-        let label = buf.to_str();
-        let img = image::open(path).expect("Couldn't find an image from path.");
+    fn load_image(assets: &mut Assets, name: &str, placeholder: bool) -> Result<DynamicImage> {
+        let img = if placeholder {
+            assets.image_or_missing(name)
+        } else {
+            assets.image(name)?.clone()
+        };
+        if img.dimensions() == (Self::TEXTURE_SIZE, Self::TEXTURE_SIZE) {
+            Ok(img)
+        } else {
+            Ok(img.resize_exact(
+                Self::TEXTURE_SIZE,
+                Self::TEXTURE_SIZE,
+                image::imageops::FilterType::Triangle,
+            ))
+        }
+    }
+
+    /// Loads the block textures into the layers of one array texture,
+    /// indexed by `TEXTURE_INDEX_LIST`.
+    /// Fails if any of the images can't be loaded.
+    pub fn load_textures(graphics: &Graphics, assets: &mut Assets) -> Result<Texture> {
+        Self::load_block_textures(graphics, assets, false)
+    }
 
-        Self::from_image(graphics, label, img)
+    /// Like `load_textures`, but replaces missing images by a placeholder.
+    /// Used at startup, so the engine runs without a complete asset folder.
+    pub fn load_textures_or_missing(graphics: &Graphics, assets: &mut Assets) -> Result<Texture> {
+        Self::load_block_textures(graphics, assets, true)
     }
 
-    pub fn load_textures(graphics: &Graphics) -> Result<Vec<Texture>> {
-        let path = std::path::Path::new(std::env::current_dir().unwrap().as_os_str()).join("res");
+    fn load_block_textures(
+        graphics: &Graphics,
+        assets: &mut Assets,
+        placeholder: bool,
+    ) -> Result<Texture> {
+        let mut images = Vec::new();
+        for name in [
+            // Grass
+            "grass_side.png",
+            "grass_bottom.png",
+            "grass_top.png",
+            // Wolf
+            "wolf.jpg",
+            // Transparent blocks
+            "leaves.png",
+            "glass.png",
+            "water.png",
+            // Lava
+            "lava.png",
+        ] {
+            images.push(Self::load_image(assets, name, placeholder)?);
+        }
+        assert_eq!(images.len() as u32, Self::TEXTURE_ARRAY_SIZE);
 
         let blit_shader = graphics
             .device
@@ -146,21 +205,12 @@ impl Texture {
                 label: Some("mipmap command encoder"),
             });
 
-        let mut textures = Vec::new();
-        // Grass
-        textures.push(Self::from_path(&graphics, path.join("grass_side.png"))?);
-        textures.push(Self::from_path(&graphics, path.join("grass_bottom.png"))?);
-        textures.push(Self::from_path(&graphics, path.join("grass_top.png"))?);
-        // Wolf
-        textures.push(Self::from_path(&graphics, path.join("wolf.jpg"))?);
-
+        let texture = Self::from_images(&graphics, Some("block texture array"), images)?;
         // Generate Mipmaps
-        for t in textures.iter() {
-            t.generate_mipmaps(&graphics, &mipmap_pipeline, &sampler, &mut encoder);
-        }
+        texture.generate_mipmaps(&graphics, &mipmap_pipeline, &sampler, &mut encoder);
         graphics.queue.submit(Some(encoder.finish()));
 
-        Ok(textures)
+        Ok(texture)
     }
 
     pub fn create_sampler(graphics: &Graphics) -> wgpu::Sampler {
@@ -184,60 +234,71 @@ impl Texture {
         encoder: &mut wgpu::CommandEncoder,
     ) {
         let bind_group_layout = pipeline.get_bind_group_layout(0);
-        let t_views = (0..self.mip_level_count)
-            .map(|mip| {
-                self.texture.create_view(&wgpu::TextureViewDescriptor {
-                    label: Some(&format!("mip level {}", mip)),
-                    format: None,
-                    dimension: None,
-                    aspect: wgpu::TextureAspect::All,
-                    base_mip_level: mip,
-                    mip_level_count: NonZeroU32::new(1),
-                    base_array_layer: 0,
-                    array_layer_count: None,
+        for layer in 0..self.layers {
+            let t_views = (0..self.mip_level_count)
+                .map(|mip| {
+                    self.texture.create_view(&wgpu::TextureViewDescriptor {
+                        label: Some(&format!("layer {} mip level {}", layer, mip)),
+                        format: None,
+                        dimension: Some(wgpu::TextureViewDimension::D2),
+                        aspect: wgpu::TextureAspect::All,
+                        base_mip_level: mip,
+                        mip_level_count: NonZeroU32::new(1),
+                        base_array_layer: layer,
+                        array_layer_count: NonZeroU32::new(1),
+                    })
                 })
-            })
-            .collect::<Vec<_>>();
+                .collect::<Vec<_>>();
 
-        for target_mip in 1..self.mip_level_count as usize {
-            let bind_group = graphics
-                .device
-                .create_bind_group(&wgpu::BindGroupDescriptor {
+            for target_mip in 1..self.mip_level_count as usize {
+                let bind_group = graphics
+                    .device
+                    .create_bind_group(&wgpu::BindGroupDescriptor {
+                        label: None,
+                        layout: &bind_group_layout,
+                        entries: &[
+                            wgpu::BindGroupEntry {
+                                binding: 0,
+                                resource: wgpu::BindingResource::TextureView(
+                                    &t_views[target_mip - 1],
+                                ),
+                            },
+                            wgpu::BindGroupEntry {
+                                binding: 1,
+                                resource: wgpu::BindingResource::Sampler(&sampler),
+                            },
+                        ],
+                    });
+                let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                     label: None,
-                    layout: &bind_group_layout,
-                    entries: &[
-                        wgpu::BindGroupEntry {
-                            binding: 0,
-                            resource: wgpu::BindingResource::TextureView(&t_views[target_mip - 1]),
-                        },
-                        wgpu::BindGroupEntry {
-                            binding: 1,
-                            resource: wgpu::BindingResource::Sampler(&sampler),
+                    color_attachments: &[wgpu::RenderPassColorAttachment {
+                        view: &t_views[target_mip],
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::GREEN),
+                            store: true,
                         },
-                    ],
+                    }],
+                    depth_stencil_attachment: None,
                 });
-            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: None,
-                color_attachments: &[wgpu::RenderPassColorAttachment {
-                    view: &t_views[target_mip],
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::GREEN),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: None,
-            });
-            pass.set_pipeline(&pipeline);
-            pass.set_bind_group(0, &bind_group, &[]);
-            pass.draw(0..3, 0..1);
+                pass.set_pipeline(&pipeline);
+                pass.set_bind_group(0, &bind_group, &[]);
+                pass.draw(0..3, 0..1);
+            }
         }
     }
 }
 
+/// Set on the texture index of faces whose surface is animated.
+pub const ANIMATED_BIT: u32 = 0x100;
+
 pub static mut TEXTURE_INDEX_LIST: Vec<[u32; 6]> = Vec::new();
 
 pub unsafe fn init_index_list() {
     TEXTURE_INDEX_LIST.push([0, 0, 0, 0, 2, 1]);
     TEXTURE_INDEX_LIST.push([1, 1, 1, 1, 1, 1]);
+    TEXTURE_INDEX_LIST.push([4, 4, 4, 4, 4, 4]);
+    TEXTURE_INDEX_LIST.push([5, 5, 5, 5, 5, 5]);
+    TEXTURE_INDEX_LIST.push([6, 6, 6, 6, 6, 6]);
+    TEXTURE_INDEX_LIST.push([7, 7, 7, 7, 7, 7]);
 }
@@ -1,8 +1,13 @@
+use crate::assets::Assets;
 use crate::camera::Camera;
+use crate::clock::Lighting;
 use crate::quad;
 use crate::renderer::graphics::Graphics;
+use crate::renderer::shadow::ShadowMap;
 use crate::texture::Texture;
-use std::num::NonZeroU32;
+use bytemuck::Zeroable;
+use futures::executor::block_on;
+use std::time::Instant;
 use wgpu::util::DeviceExt;
 
 pub struct RenderPassData {
@@ -12,10 +17,12 @@ pub struct RenderPassData {
 
     global_matrix: GlobalMatrix,
     texture_array: SampledTextureArray,
+    pub shadow: ShadowMap,
+    start_time: Instant,
 }
 
 impl RenderPassData {
-    pub fn new(graphics: &Graphics, camera: &Camera) -> Self {
+    pub fn new(graphics: &Graphics, assets: &mut Assets) -> Self {
         let face_vertex_buffer =
             graphics
                 .device
@@ -33,10 +40,10 @@ impl RenderPassData {
                     usage: wgpu::BufferUsages::INDEX,
                 });
         let indices_len = quad::INDICES.len() as u32;
-        let global_matrix = GlobalMatrix::new(&graphics, &camera);
+        let global_matrix = GlobalMatrix::new(&graphics);
         let texture_array = SampledTextureArray::new(
             &graphics,
-            Texture::load_textures(&graphics).unwrap(),
+            Texture::load_textures_or_missing(&graphics, assets).unwrap(),
             Texture::create_sampler(&graphics),
         );
 
@@ -46,17 +53,54 @@ impl RenderPassData {
             indices_len,
             global_matrix,
             texture_array,
+            shadow: ShadowMap::new(&graphics),
+            start_time: Instant::now(),
         }
     }
 
-    pub fn update(&mut self, camera: &Camera, graphics: &Graphics) {
-        self.global_matrix.update(&camera, &graphics);
+    /// Updates the camera, fog and light uniforms. Fog is fully opaque at
+    /// `fog_distance`.
+    pub fn update(
+        &mut self,
+        camera: &Camera,
+        graphics: &Graphics,
+        fog_distance: f32,
+        lighting: &Lighting,
+    ) {
+        let time = self.start_time.elapsed().as_secs_f32();
+        self.global_matrix
+            .update(&camera, &graphics, time, fog_distance, &lighting);
+        self.shadow.update(&graphics, &camera, &lighting);
+    }
+
+    /// Loads the block textures again. If any image is missing or broken the
+    /// current textures are kept and the error is returned.
+    pub fn reload_textures(
+        &mut self,
+        graphics: &Graphics,
+        assets: &mut Assets,
+    ) -> Result<(), String> {
+        assets.clear_cache();
+        graphics
+            .device
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        let texture = Texture::load_textures(&graphics, assets);
+        let error = block_on(graphics.device.pop_error_scope());
+        match (texture, error) {
+            (Ok(texture), None) => {
+                self.texture_array.set_texture(&graphics, texture);
+                Ok(())
+            }
+            (Err(e), _) => Err(format!("textures: {:#}", e)),
+            (_, Some(e)) => Err(format!("textures: {}", e)),
+        }
     }
 
     pub fn bind_group_layouts(&self) -> Vec<&wgpu::BindGroupLayout> {
         let mut layouts = Vec::new();
         layouts.push(&self.global_matrix.bind_group_layout);
         layouts.push(&self.texture_array.bind_group_layout);
+        layouts.push(&self.shadow.bind_group_layout);
         layouts
     }
 }
@@ -69,6 +113,7 @@ impl<'a> SetUniforms<'a> for wgpu::RenderPass<'a> {
     fn set_bind_groups(&mut self, render_data: &'a RenderPassData) {
         self.set_bind_group(0, &render_data.global_matrix.bind_group, &[]);
         self.set_bind_group(1, &render_data.texture_array.bind_group, &[]);
+        self.set_bind_group(2, &render_data.shadow.bind_group, &[]);
     }
 }
 
@@ -83,16 +128,43 @@ struct Uniform<T> {
 #[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct MatrixData {
     pub proj_view_model_matrix: [[f32; 4]; 4],
+    pub eye: [f32; 4],
+    pub fog_color: [f32; 4],
+    pub sun_direction: [f32; 4],
+    // Seconds since start, used to animate fluid surfaces.
+    pub time: f32,
+    pub fog_density: f32,
+    pub ambient: f32,
+    pub skylight: f32,
+}
+
+// exp(-FOG_EXTENT^2) of the color is left at the fog distance.
+const FOG_EXTENT: f32 = 2.2;
+
+impl MatrixData {
+    fn new(camera: &Camera, time: f32, fog_distance: f32, lighting: &Lighting) -> Self {
+        let fog = lighting.horizon_color;
+        let sun = lighting.sun_direction;
+        Self {
+            proj_view_model_matrix: camera.global_matrix.into(),
+            eye: [camera.eye.x, camera.eye.y, camera.eye.z, 1.],
+            // Terrain fades into the sky at the horizon.
+            fog_color: [fog[0], fog[1], fog[2], 1.],
+            sun_direction: [sun.x, sun.y, sun.z, 0.],
+            time,
+            fog_density: FOG_EXTENT / fog_distance,
+            ambient: lighting.ambient,
+            skylight: lighting.skylight,
+        }
+    }
 }
 
 type GlobalMatrix = Uniform<MatrixData>;
 
 impl GlobalMatrix {
-    pub fn new(graphics: &Graphics, camera: &Camera) -> Self {
-        let proj_view_model_matrix: [[f32; 4]; 4] = camera.global_matrix.into();
-        let data = MatrixData {
-            proj_view_model_matrix,
-        };
+    pub fn new(graphics: &Graphics) -> Self {
+        // Filled in by the first update before anything is drawn.
+        let data = MatrixData::zeroed();
 
         let buffer = graphics
             .device
@@ -136,11 +208,15 @@ impl GlobalMatrix {
         }
     }
 
-    fn update(&mut self, camera: &Camera, graphics: &Graphics) {
-        let proj_view_model_matrix: [[f32; 4]; 4] = camera.global_matrix.into();
-        self.data = MatrixData {
-            proj_view_model_matrix,
-        };
+    fn update(
+        &mut self,
+        camera: &Camera,
+        graphics: &Graphics,
+        time: f32,
+        fog_distance: f32,
+        lighting: &Lighting,
+    ) {
+        self.data = MatrixData::new(&camera, time, fog_distance, &lighting);
         graphics.queue.write_buffer(
             &self.buffer.as_ref().unwrap(),
             0,
@@ -150,22 +226,15 @@ impl GlobalMatrix {
 }
 
 pub struct TextureArray {
-    textures: Vec<Texture>,
+    texture: Texture,
     sampler: wgpu::Sampler,
 }
 
-impl TextureArray {
-    fn get_ref(&self) -> Vec<&wgpu::TextureView> {
-        let array = self.textures.iter().map(|t| &t.view).collect::<Vec<_>>();
-        array
-    }
-}
-
 type SampledTextureArray = Uniform<TextureArray>;
 
 impl SampledTextureArray {
-    pub fn new(graphics: &Graphics, textures: Vec<Texture>, sampler: wgpu::Sampler) -> Self {
-        let data = TextureArray { textures, sampler };
+    pub fn new(graphics: &Graphics, texture: Texture, sampler: wgpu::Sampler) -> Self {
+        let data = TextureArray { texture, sampler };
         let bind_group_layout =
             graphics
                 .device
@@ -186,19 +255,41 @@ impl SampledTextureArray {
                             visibility: wgpu::ShaderStages::FRAGMENT,
                             ty: wgpu::BindingType::Texture {
                                 sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                                view_dimension: wgpu::TextureViewDimension::D2,
+                                view_dimension: wgpu::TextureViewDimension::D2Array,
                                 multisampled: false,
                             },
-                            count: NonZeroU32::new(Texture::TEXTURE_ARRAY_SIZE),
+                            count: None,
                         },
                     ],
                 });
 
-        let bind_group = graphics
+        let bind_group =
+            SampledTextureArray::create_bind_group(&graphics, &bind_group_layout, &data);
+
+        Self {
+            bind_group_layout,
+            bind_group,
+            buffer: None,
+            data,
+        }
+    }
+
+    fn set_texture(&mut self, graphics: &Graphics, texture: Texture) {
+        self.data.texture = texture;
+        self.bind_group =
+            SampledTextureArray::create_bind_group(&graphics, &self.bind_group_layout, &self.data);
+    }
+
+    fn create_bind_group(
+        graphics: &Graphics,
+        layout: &wgpu::BindGroupLayout,
+        data: &TextureArray,
+    ) -> wgpu::BindGroup {
+        graphics
             .device
             .create_bind_group(&wgpu::BindGroupDescriptor {
                 label: Some("Sampled texture array bind group"),
-                layout: &bind_group_layout,
+                layout,
                 entries: &[
                     wgpu::BindGroupEntry {
                         binding: 0,
@@ -206,16 +297,9 @@ impl SampledTextureArray {
                     },
                     wgpu::BindGroupEntry {
                         binding: 1,
-                        resource: wgpu::BindingResource::TextureViewArray(&data.get_ref()),
+                        resource: wgpu::BindingResource::TextureView(&data.texture.view),
                     },
                 ],
-            });
-
-        Self {
-            bind_group_layout,
-            bind_group,
-            buffer: None,
-            data,
-        }
+            })
     }
 }
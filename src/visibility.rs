@@ -0,0 +1,296 @@
+use crate::coordinate::ChunkCoord3D;
+use crate::cube::Cube;
+use crate::frustum_culling::Frustum;
+use crate::world::CHUNK_USIZE;
+use hashbrown::HashSet;
+use std::collections::VecDeque;
+
+// Face indices, in the same order as the adjacent chunks and the texture
+// indices used while meshing.
+pub const LEFT: usize = 0;
+pub const RIGHT: usize = 1;
+pub const BACK: usize = 2;
+pub const FRONT: usize = 3;
+pub const UP: usize = 4;
+pub const DOWN: usize = 5;
+
+const FACE_OFFSETS: [(i32, i32, i32); 6] = [
+    (-1, 0, 0),
+    (1, 0, 0),
+    (0, 0, -1),
+    (0, 0, 1),
+    (0, 1, 0),
+    (0, -1, 0),
+];
+
+fn opposite(face: usize) -> usize {
+    face ^ 1
+}
+
+/// Which pairs of chunk faces are connected through see-through cubes
+/// inside a chunk.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ChunkVisibility(u64);
+
+impl ChunkVisibility {
+    pub const NONE: ChunkVisibility = ChunkVisibility(0);
+    pub const ALL: ChunkVisibility = ChunkVisibility((1 << 36) - 1);
+
+    /// Flood fills every see-through region of the chunk and connects all faces
+    /// each region touches.
+    pub fn from_cubes(cubes: &[Cube]) -> Self {
+        let mut visibility = ChunkVisibility::NONE;
+        let mut visited = vec![false; cubes.len()];
+        let mut stack = Vec::new();
+        for start in 0..cubes.len() {
+            if visited[start] || cubes[start].cube_type.is_opaque() {
+                continue;
+            }
+            visited[start] = true;
+            stack.push(start);
+            let mut touched = 0u8;
+            while let Some(i) = stack.pop() {
+                let x = i % CHUNK_USIZE;
+                let z = (i / CHUNK_USIZE) % CHUNK_USIZE;
+                let y = i / (CHUNK_USIZE * CHUNK_USIZE);
+                let mut neighbours = [None; 6];
+                if x > 0 {
+                    neighbours[LEFT] = Some(i - 1);
+                }
+                if x < CHUNK_USIZE - 1 {
+                    neighbours[RIGHT] = Some(i + 1);
+                }
+                if z > 0 {
+                    neighbours[BACK] = Some(i - CHUNK_USIZE);
+                }
+                if z < CHUNK_USIZE - 1 {
+                    neighbours[FRONT] = Some(i + CHUNK_USIZE);
+                }
+                if y < CHUNK_USIZE - 1 {
+                    neighbours[UP] = Some(i + CHUNK_USIZE * CHUNK_USIZE);
+                }
+                if y > 0 {
+                    neighbours[DOWN] = Some(i - CHUNK_USIZE * CHUNK_USIZE);
+                }
+                for (face, n) in neighbours.iter().enumerate() {
+                    match n {
+                        Some(n) => {
+                            if !visited[*n] && !cubes[*n].cube_type.is_opaque() {
+                                visited[*n] = true;
+                                stack.push(*n);
+                            }
+                        }
+                        // The cell lies on this face of the chunk.
+                        None => touched |= 1 << face,
+                    }
+                }
+            }
+            for a in 0..6 {
+                for b in 0..6 {
+                    if touched & (1 << a) != 0 && touched & (1 << b) != 0 {
+                        visibility.connect(a, b);
+                    }
+                }
+            }
+            if visibility == ChunkVisibility::ALL {
+                break;
+            }
+        }
+        visibility
+    }
+
+    fn connect(&mut self, a: usize, b: usize) {
+        self.0 |= 1 << (a * 6 + b);
+        self.0 |= 1 << (b * 6 + a);
+    }
+
+    pub fn is_connected(&self, a: usize, b: usize) -> bool {
+        self.0 & (1 << (a * 6 + b)) != 0
+    }
+}
+
+struct Node {
+    pos: ChunkCoord3D,
+    entered_from: Option<usize>,
+    directions: u8,
+}
+
+/// Walks the chunk visibility graph outwards from the camera chunk and
+/// returns every chunk that can be seen through see-through cubes. Chunks
+/// for which `visibility` returns `None`, e.g. because they aren't meshed
+/// yet, are treated as empty.
+pub fn visible_chunks<F>(
+    visibility: F,
+    camera_chunk: ChunkCoord3D,
+    frustum: &Frustum,
+    render_distance: i32,
+) -> HashSet<ChunkCoord3D>
+where
+    F: Fn(&ChunkCoord3D) -> Option<ChunkVisibility>,
+{
+    let mut visible = HashSet::new();
+    let mut queue = VecDeque::new();
+    visible.insert(camera_chunk);
+    queue.push_back(Node {
+        pos: camera_chunk,
+        entered_from: None,
+        directions: 0,
+    });
+    while let Some(node) = queue.pop_front() {
+        let chunk_visibility = visibility(&node.pos).unwrap_or(ChunkVisibility::ALL);
+        for (face, offset) in FACE_OFFSETS.iter().enumerate() {
+            // Never walk back towards the camera.
+            if node.directions & (1 << opposite(face)) != 0 {
+                continue;
+            }
+            if let Some(from) = node.entered_from {
+                if !chunk_visibility.is_connected(from, face) {
+                    continue;
+                }
+            }
+            let next = ChunkCoord3D::new(
+                node.pos.x + offset.0,
+                node.pos.y + offset.1,
+                node.pos.z + offset.2,
+            );
+            if (next.x - camera_chunk.x).abs() > render_distance
+                || (next.y - camera_chunk.y).abs() > render_distance
+                || (next.z - camera_chunk.z).abs() > render_distance
+            {
+                continue;
+            }
+            if visible.contains(&next) || !frustum.contains(&next) {
+                continue;
+            }
+            visible.insert(next);
+            queue.push_back(Node {
+                pos: next,
+                entered_from: Some(opposite(face)),
+                directions: node.directions | (1 << face),
+            });
+        }
+    }
+    visible
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cube::CubeType;
+
+    // Chunk filled with dirt, except where `is_air` is true.
+    fn cubes(is_air: impl Fn(usize, usize, usize) -> bool) -> Vec<Cube> {
+        let mut cubes = Vec::new();
+        for y in 0..CHUNK_USIZE {
+            for z in 0..CHUNK_USIZE {
+                for x in 0..CHUNK_USIZE {
+                    let cube_type = if is_air(x, y, z) {
+                        CubeType::AIR
+                    } else {
+                        CubeType::DIRT
+                    };
+                    cubes.push(Cube::new(cube_type));
+                }
+            }
+        }
+        cubes
+    }
+
+    // Tunnel through the middle of the chunk from its back to its front.
+    fn tunnel() -> ChunkVisibility {
+        ChunkVisibility::from_cubes(&cubes(|x, y, _| x == 16 && y == 16))
+    }
+
+    fn sealed() -> ChunkVisibility {
+        // Hollow inside, but the cave doesn't reach any face.
+        ChunkVisibility::from_cubes(&cubes(|x, y, z| {
+            (4..28).contains(&x) && (4..28).contains(&y) && (4..28).contains(&z)
+        }))
+    }
+
+    #[test]
+    fn empty_chunk_connects_all_faces() {
+        assert_eq!(
+            ChunkVisibility::from_cubes(&cubes(|_, _, _| true)),
+            ChunkVisibility::ALL
+        );
+    }
+
+    #[test]
+    fn sealed_chunk_connects_no_faces() {
+        assert_eq!(sealed(), ChunkVisibility::NONE);
+    }
+
+    #[test]
+    fn tunnel_connects_only_its_ends() {
+        let visibility = tunnel();
+        assert!(visibility.is_connected(BACK, FRONT));
+        assert!(visibility.is_connected(FRONT, BACK));
+        assert!(!visibility.is_connected(BACK, LEFT));
+        assert!(!visibility.is_connected(LEFT, RIGHT));
+        assert!(!visibility.is_connected(UP, DOWN));
+    }
+
+    #[test]
+    fn sealed_chunk_hides_chunks_behind_it() {
+        let f = Frustum::looking_at([16., 16., 16.], [16., 16., -100.], 90., 1000.);
+        let blocker = ChunkCoord3D::new(0, 0, -1);
+        let visible = visible_chunks(
+            |p| if *p == blocker { Some(sealed()) } else { None },
+            ChunkCoord3D::new(0, 0, 0),
+            &f,
+            4,
+        );
+        assert!(visible.contains(&blocker));
+        assert!(!visible.contains(&ChunkCoord3D::new(0, 0, -2)));
+        assert!(visible.contains(&ChunkCoord3D::new(1, 0, -2)));
+    }
+
+    #[test]
+    fn tunnel_shows_chunks_behind_it() {
+        let f = Frustum::looking_at([16., 16., 16.], [16., 16., -100.], 90., 1000.);
+        let visible = visible_chunks(
+            |p| {
+                if *p == ChunkCoord3D::new(0, 0, -1) {
+                    Some(tunnel())
+                } else {
+                    None
+                }
+            },
+            ChunkCoord3D::new(0, 0, 0),
+            &f,
+            4,
+        );
+        assert!(visible.contains(&ChunkCoord3D::new(0, 0, -2)));
+    }
+
+    #[test]
+    fn camera_chunk_on_border_sees_its_neighbour() {
+        // The eye sits on the border of a sealed chunk, its own chunk never
+        // blocks the view.
+        let f = Frustum::looking_at([16., 16., 0.01], [16., 16., -100.], 90., 1000.);
+        let camera_chunk = ChunkCoord3D::new(0, 0, 0);
+        let visible = visible_chunks(
+            |p| {
+                if *p == camera_chunk {
+                    Some(sealed())
+                } else {
+                    None
+                }
+            },
+            camera_chunk,
+            &f,
+            4,
+        );
+        assert!(visible.contains(&ChunkCoord3D::new(0, 0, -1)));
+        assert!(visible.contains(&ChunkCoord3D::new(0, 0, -2)));
+    }
+
+    #[test]
+    fn chunks_past_render_distance_are_hidden() {
+        let f = Frustum::looking_at([16., 16., 16.], [16., 16., -100.], 90., 1000.);
+        let visible = visible_chunks(|_| None, ChunkCoord3D::new(0, 0, 0), &f, 2);
+        assert!(visible.contains(&ChunkCoord3D::new(0, 0, -2)));
+        assert!(!visible.contains(&ChunkCoord3D::new(0, 0, -3)));
+    }
+}
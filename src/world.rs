@@ -1,22 +1,65 @@
 use crate::chunk::{Chunk, ChunkMesh};
 use crate::chunk_builder::ChunkGenerator;
-use crate::coordinate::ChunkCoord3D;
+use crate::clock::WorldClock;
+use crate::coordinate::{ChunkCoord3D, Coord3DF, Coord3DI};
+use crate::cube::Cube;
 use crate::frustum_culling::Frustum;
 use crate::player::Player;
 use crate::renderer::graphics::Graphics;
+use crate::renderer::indirect::IndirectRenderer;
 use crate::renderer::pipeline::Type;
 use crate::renderer::renderer::{Renderable, Renderer};
 use crate::uniform::RenderPassData;
-use hashbrown::HashMap;
+use crate::visibility;
+use hashbrown::{HashMap, HashSet};
 use std::sync::Arc;
 use wgpu::RenderPass;
 
 pub struct World {
     pub chunks: HashMap<ChunkCoord3D, Arc<Chunk>>,
     pub meshes: HashMap<ChunkCoord3D, ChunkMesh>,
+    // Only present when the adapter supports multi draw indirect.
+    pub indirect: Option<IndirectRenderer>,
+    pub occlusion_culling: bool,
+    visible_chunks: HashSet<ChunkCoord3D>,
+    // Number of chunks which passed culling, see `count_drawn_chunks`.
+    pub drawn_chunks: usize,
+    // Chunks with translucent faces, sorted back to front.
+    translucent_order: Vec<ChunkCoord3D>,
+    pub render_distance: i32,
+    pub clock: WorldClock,
 }
 
-pub const RENDER_DISTANCE: i32 = 5;
+pub const DEFAULT_RENDER_DISTANCE: i32 = 5;
+pub const MIN_RENDER_DISTANCE: i32 = 2;
+pub const MAX_RENDER_DISTANCE: i32 = 32;
+// Extra chunks past the render distance before chunks get unloaded.
+pub const UNLOAD_MARGIN: i32 = 2;
+
+/// Cylindrical distance check in chunks, horizontal distance is measured
+/// as a circle and vertical distance separately.
+pub fn is_within_radius(center: ChunkCoord3D, pos: ChunkCoord3D, radius: i32) -> bool {
+    let dx = pos.x - center.x;
+    let dz = pos.z - center.z;
+    dx * dx + dz * dz <= radius * radius && (pos.y - center.y).abs() <= radius
+}
+
+// Chunk distances up to which each LOD level is used. Chunks further away
+// than the last ring are meshed at the lowest detail.
+pub const LOD_RINGS: [i32; 3] = [2, 4, 6];
+
+/// LOD level of a chunk, 0 being full resolution and `n` merging blocks
+/// of `2^n` cubes along each axis.
+pub fn lod_level(center: ChunkCoord3D, pos: ChunkCoord3D) -> u32 {
+    let distance = (pos.x - center.x)
+        .abs()
+        .max((pos.y - center.y).abs())
+        .max((pos.z - center.z).abs());
+    LOD_RINGS
+        .iter()
+        .position(|&r| distance <= r)
+        .unwrap_or(LOD_RINGS.len()) as u32
+}
 
 pub const CHUNK_USIZE: usize = 32;
 pub const CHUNK_I32: i32 = CHUNK_USIZE as i32;
@@ -29,21 +72,240 @@ impl Renderable for World {
         uniform: &'a RenderPassData,
         frustum: &'a Frustum,
     ) {
-        pass.set_pipeline(&renderer.pipelines.get(&Type::Main).unwrap().pipeline);
+        let view = renderer.view;
+        if view.draws_scene() {
+            pass.set_pipeline(&renderer.pipelines.get(&Type::Main).unwrap().pipeline);
+        } else {
+            pass.set_pipeline(&renderer.pipelines.get(&view).unwrap().pipeline);
+        }
 
-        for (p, c) in self.meshes.iter() {
-            if frustum.contains(p) {
-                c.render(pass, &uniform);
+        if let Some(indirect) = &self.indirect {
+            indirect.render(pass, &uniform);
+        } else {
+            for (p, c) in self.meshes.iter() {
+                if frustum.contains(p) && self.is_visible(p) {
+                    c.render(pass, &uniform);
+                }
             }
         }
+
+        if view.draws_scene() {
+            pass.set_pipeline(&renderer.pipelines.get(&Type::Translucent).unwrap().pipeline);
+        }
+        for p in self.translucent_order.iter() {
+            self.meshes[p].render_translucent(pass, &uniform);
+        }
     }
 }
 
 impl World {
-    pub fn new() -> Self {
+    pub fn new(graphics: &Graphics) -> Self {
         let chunks = HashMap::new();
         let meshes = HashMap::new();
-        Self { chunks, meshes }
+        let indirect = if IndirectRenderer::is_supported(&graphics) {
+            Some(IndirectRenderer::new(&graphics))
+        } else {
+            None
+        };
+        Self {
+            chunks,
+            meshes,
+            indirect,
+            occlusion_culling: true,
+            visible_chunks: HashSet::new(),
+            drawn_chunks: 0,
+            translucent_order: Vec::new(),
+            render_distance: DEFAULT_RENDER_DISTANCE,
+            clock: WorldClock::new(),
+        }
+    }
+
+    /// World without chunks or GPU resources, for tests.
+    #[cfg(test)]
+    pub fn empty() -> Self {
+        Self {
+            chunks: HashMap::new(),
+            meshes: HashMap::new(),
+            indirect: None,
+            occlusion_culling: true,
+            visible_chunks: HashSet::new(),
+            drawn_chunks: 0,
+            translucent_order: Vec::new(),
+            render_distance: DEFAULT_RENDER_DISTANCE,
+            clock: WorldClock::new(),
+        }
+    }
+
+    /// The 27 chunks around the origin filled with air, for tests which
+    /// build their own terrain.
+    #[cfg(test)]
+    pub fn with_empty_chunks() -> Self {
+        let mut world = World::empty();
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let pos = ChunkCoord3D::new(x, y, z);
+                    world.chunks.insert(pos, Arc::new(Chunk::empty(pos)));
+                }
+            }
+        }
+        world
+    }
+
+    /// Changes the render distance. Chunks are loaded and unloaded
+    /// gradually by the chunk generator in the following ticks.
+    pub fn set_render_distance(&mut self, render_distance: i32) {
+        self.render_distance = render_distance
+            .max(MIN_RENDER_DISTANCE)
+            .min(MAX_RENDER_DISTANCE);
+    }
+
+    /// Far plane distance that covers every chunk within render distance.
+    pub fn view_distance(&self) -> f32 {
+        ((self.render_distance + 1) * CHUNK_I32) as f32 * std::f32::consts::SQRT_2
+    }
+
+    /// Distance at which terrain has fully faded into the sky, just
+    /// before chunks get streamed in.
+    pub fn fog_distance(&self) -> f32 {
+        (self.render_distance * CHUNK_I32) as f32
+    }
+
+    fn is_visible(&self, pos: &ChunkCoord3D) -> bool {
+        !self.occlusion_culling || self.visible_chunks.contains(pos)
+    }
+
+    // Chunk and position inside of it for a world position.
+    fn locate(pos: Coord3DI) -> (ChunkCoord3D, usize, usize, usize) {
+        (
+            pos.to_chunk_coord(),
+            pos.x.rem_euclid(CHUNK_I32) as usize,
+            pos.y.rem_euclid(CHUNK_I32) as usize,
+            pos.z.rem_euclid(CHUNK_I32) as usize,
+        )
+    }
+
+    /// Cube at a world position, `None` if its chunk isn't loaded.
+    pub fn cube_at(&self, pos: Coord3DI) -> Option<&Cube> {
+        let (chunk, x, y, z) = World::locate(pos);
+        self.chunks.get(&chunk).map(|c| c.cube(x, y, z))
+    }
+
+    /// Replaces the cube at a world position. Returns false if its chunk
+    /// isn't loaded. The chunk mesh has to be rebuilt separately.
+    pub fn set_cube(&mut self, pos: Coord3DI, cube: Cube) -> bool {
+        let (chunk, x, y, z) = World::locate(pos);
+        match self.chunks.get_mut(&chunk) {
+            Some(c) => {
+                // Chunks still shared with a mesh builder are copied.
+                Arc::make_mut(c).set_cube(x, y, z, cube);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn insert_mesh(&mut self, pos: ChunkCoord3D, mesh: ChunkMesh) {
+        self.meshes.insert(pos, mesh);
+        if let Some(indirect) = &mut self.indirect {
+            indirect.invalidate(pos);
+        }
+    }
+
+    /// Runs the occlusion culling from the camera chunk and builds the
+    /// indirect draw list of visible chunks for this frame.
+    pub fn prepare_render(
+        &mut self,
+        graphics: &Graphics,
+        frustum: &Frustum,
+        uniform: &RenderPassData,
+        eye: Coord3DF,
+    ) {
+        let camera_chunk = eye.to_chunk_coord();
+        if self.occlusion_culling {
+            let meshes = &self.meshes;
+            self.visible_chunks = visibility::visible_chunks(
+                |p| meshes.get(p).map(|m| m.visibility),
+                camera_chunk,
+                &frustum,
+                self.render_distance,
+            );
+        }
+        self.sort_translucent(graphics, frustum, eye);
+
+        if let Some(indirect) = &mut self.indirect {
+            let visible = if self.occlusion_culling {
+                Some(&self.visible_chunks)
+            } else {
+                None
+            };
+            indirect.prepare(
+                &graphics,
+                &self.meshes,
+                &frustum,
+                uniform.indices_len,
+                visible,
+            );
+        }
+    }
+
+    /// Updates `drawn_chunks`, costs a frustum test per chunk without
+    /// occlusion culling so only done while the count is shown.
+    pub fn count_drawn_chunks(&mut self, frustum: &Frustum) {
+        // Visible chunks are already frustum culled. Chunks without opaque
+        // faces aren't packed for the indirect draw, so aren't counted.
+        let has_faces =
+            |p: &ChunkCoord3D| self.meshes.get(p).map_or(false, |m| m.instances_len > 0);
+        self.drawn_chunks = if self.occlusion_culling {
+            self.visible_chunks.iter().filter(|p| has_faces(p)).count()
+        } else {
+            self.meshes
+                .keys()
+                .filter(|p| has_faces(p) && frustum.contains(p))
+                .count()
+        };
+    }
+
+    // Blending needs the translucent faces drawn back to front. Chunks are
+    // sorted by the distance to their center, then the faces within each.
+    fn sort_translucent(&mut self, graphics: &Graphics, frustum: &Frustum, eye: Coord3DF) {
+        let half = CHUNK_I32 as f32 / 2.;
+        let distance = |p: &ChunkCoord3D| {
+            let pos = p.to_world_position_f32();
+            let dx = pos.x + half - eye.x;
+            let dy = pos.y + half - eye.y;
+            let dz = pos.z + half - eye.z;
+            dx * dx + dy * dy + dz * dz
+        };
+        let meshes = &self.meshes;
+        let mut order = meshes
+            .iter()
+            .filter(|(p, m)| m.translucent_len > 0 && frustum.contains(p) && self.is_visible(p))
+            .map(|(p, _)| *p)
+            .collect::<Vec<_>>();
+        order.sort_by(|a, b| distance(b).partial_cmp(&distance(a)).unwrap());
+        for p in order.iter() {
+            if let Some(mesh) = self.meshes.get_mut(p) {
+                mesh.sort_translucent(&graphics.queue, eye);
+            }
+        }
+        self.translucent_order = order;
+    }
+
+    /// Draws the opaque faces of every chunk inside the light space bounds
+    /// of a shadow cascade. Chunks outside of the view can still cast
+    /// shadows into it.
+    pub fn render_shadow_casters<'a>(
+        &'a self,
+        pass: &mut RenderPass<'a>,
+        uniform: &'a RenderPassData,
+        frustum: &Frustum,
+    ) {
+        for (p, mesh) in self.meshes.iter() {
+            if mesh.instances_len > 0 && frustum.contains(p) {
+                mesh.render_depth(pass, &uniform);
+            }
+        }
     }
 
     pub fn update(